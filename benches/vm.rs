@@ -0,0 +1,53 @@
+#[macro_use]
+extern crate criterion;
+extern crate secd;
+
+use criterion::Criterion;
+
+const FIB: &str = "
+(define fib
+  (lambda n
+    (if (eq n 0) 0
+    (if (eq n 1) 1
+    (+ (fib (- n 1)) (fib (- n 2)))))))
+(fib 20)";
+
+// there is no < on integers, so tak compares small naturals as chars
+const TAK: &str = "
+(define lt (lambda (a b) (char<? (integer->char a) (integer->char b))))
+(define tak
+  (lambda (x y z)
+    (if (lt y x)
+      (tak (tak (- x 1) y z) (tak (- y 1) z x) (tak (- z 1) x y))
+      z)))
+(tak 18 12 6)";
+
+const ACKERMANN: &str = "
+(define ack
+  (lambda (m n)
+    (if (eq m 0) (+ n 1)
+    (if (eq n 0) (ack (- m 1) 1)
+    (ack (- m 1) (ack m (- n 1)))))))
+(ack 2 9)";
+
+const LIST: &str = "
+(define build (lambda (n acc) (if (eq n 0) acc (build (- n 1) (cons n acc)))))
+(define len (lambda (l acc) (if (eq l nil) acc (len (cdr l) (+ acc 1)))))
+(len (build 10000 nil) 0)";
+
+fn bench(c: &mut Criterion, name: &str, src: &str) {
+    let code = secd::compile_lisp(src).unwrap();
+    c.bench_function(name, |b| {
+        b.iter(|| secd::Interpreter::new().eval_code(code.clone()).unwrap())
+    });
+}
+
+fn benches(c: &mut Criterion) {
+    bench(c, "fib", FIB);
+    bench(c, "tak", TAK);
+    bench(c, "ackermann", ACKERMANN);
+    bench(c, "list", LIST);
+}
+
+criterion_group!(vm, benches);
+criterion_main!(vm);
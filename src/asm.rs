@@ -0,0 +1,326 @@
+use data::{Code, CodeOP, CodeOPInfo, Info, Value, SIMPLE_OPS, char_from_name};
+use symbol::Symbol;
+use verify::verify;
+
+use std::rc::Rc;
+use std::collections::HashMap;
+use std::error::Error;
+
+// A small assembly language for CodeOP, e.g.
+//
+//   LDC 1
+//   ARGS 1
+//   CLOSURE (x) { LD x LDC 1 ADD RET }
+//   AP
+//
+// Jump and closure offsets are given as a number or as a label, written
+// `name:` before the instruction to continue at. A closure body may also be
+// written inline in braces. Listings produced by `disassemble` assemble back
+// to the same code: a leading instruction index is skipped and a line:col
+// before an instruction is used as its Info. Otherwise an instruction gets
+// the position of its mnemonic. `;` starts a comment.
+//
+// The result must pass verify, so offsets cannot leave the code or the body
+// they are written in, and the stack cannot underflow.
+
+type AsmResult<T> = Result<T, Box<dyn Error>>;
+
+struct Token {
+    info: Info,
+    text: String,
+}
+
+enum Target {
+    Offset(usize),
+    Label(Info, String),
+}
+
+enum Op {
+    Plain(CodeOP),
+    Closure(Rc<Box<[Symbol]>>, Target),
+    Jmp(Target),
+    Jmpf(Target),
+}
+
+fn error<T>(info: &Info, msg: &str) -> AsmResult<T> {
+    Err(From::from(format!("{}:{}:asm error: {}", info[0], info[1], msg)))
+}
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || c == '{' || c == '}' || c == '(' || c == ')' || c == ';'
+}
+
+fn tokenize(src: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = src.chars().peekable();
+    let mut info = [1, 0];
+    let mut comment = false;
+    while let Some(c) = chars.next() {
+        if c == '\n' {
+            info = [info[0] + 1, 0];
+            comment = false;
+            continue;
+        }
+        info[1] += 1;
+        let start = info;
+        if comment || c.is_whitespace() {
+            continue;
+        }
+        if c == ';' {
+            comment = true;
+            continue;
+        }
+
+        let mut text = c.to_string();
+        if !is_delimiter(c) {
+            // the character after #\ may itself be a delimiter
+            if c == '#' && chars.peek() == Some(&'\\') {
+                text.push(chars.next().unwrap());
+                info[1] += 1;
+                if let Some(c) = chars.next() {
+                    text.push(c);
+                    info[1] += 1;
+                }
+            }
+            while let Some(&c) = chars.peek() {
+                if is_delimiter(c) {
+                    break;
+                }
+                text.push(c);
+                chars.next();
+                info[1] += 1;
+            }
+        }
+        tokens.push(Token { info: start, text });
+    }
+
+    tokens
+}
+
+fn parse_info(s: &str) -> Option<Info> {
+    let mut parts = s.splitn(2, ':');
+    match (parts.next()?.parse(), parts.next()?.parse()) {
+        (Ok(line), Ok(col)) => Some([line, col]),
+        _ => None,
+    }
+}
+
+fn parse_const(info: &Info, s: &str) -> AsmResult<Value> {
+    let val = match s {
+        "nil" => Value::Nil,
+        "true" => Value::True,
+        "false" => Value::False,
+        _ if s.starts_with("#\\") => {
+            match char_from_name(&s[2..]) {
+                Some(c) => Value::Char(c),
+                None => return error(info, &format!("unknown char name '{}'", &s[2..])),
+            }
+        }
+        _ => {
+            match s.parse() {
+                Ok(n) => Value::Int(n),
+                Err(_) => return error(info, &format!("bad constant '{}'", s)),
+            }
+        }
+    };
+    Ok(val)
+}
+
+struct Assembler {
+    tokens: Vec<Token>,
+    pos: usize,
+    ops: Vec<(Info, Op)>,
+    labels: HashMap<String, usize>,
+}
+
+impl Assembler {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self, what: &str) -> AsmResult<&Token> {
+        self.pos += 1;
+        match self.tokens.get(self.pos - 1) {
+            Some(t) => Ok(t),
+            None => {
+                let info = self.tokens.last().map(|t| t.info).unwrap_or([1, 0]);
+                error(&info, &format!("expected {}, got end of input", what))
+            }
+        }
+    }
+
+    fn expect(&mut self, text: &str) -> AsmResult<()> {
+        let t = self.next(&format!("'{}'", text))?;
+        if t.text != text {
+            return error(&t.info, &format!("expected '{}', got '{}'", text, t.text));
+        }
+        Ok(())
+    }
+
+    fn word(&mut self, what: &str) -> AsmResult<(Info, String)> {
+        let t = self.next(what)?;
+        if t.text.len() == 1 && is_delimiter(t.text.chars().next().unwrap()) {
+            return error(&t.info, &format!("expected {}, got '{}'", what, t.text));
+        }
+        Ok((t.info, t.text.clone()))
+    }
+
+    fn symbol(&mut self) -> AsmResult<Symbol> {
+        Ok(Symbol::intern(&self.word("an identifier")?.1))
+    }
+
+    fn number(&mut self) -> AsmResult<usize> {
+        let (info, s) = self.word("a number")?;
+        match s.parse() {
+            Ok(n) => Ok(n),
+            Err(_) => error(&info, &format!("expected a number, got '{}'", s)),
+        }
+    }
+
+    fn target(&mut self) -> AsmResult<Target> {
+        let (info, s) = self.word("an offset or label")?;
+        match s.parse() {
+            Ok(n) => Ok(Target::Offset(n)),
+            Err(_) => Ok(Target::Label(info, s)),
+        }
+    }
+
+    // Reads labels and instructions up to the end of input or a closing
+    // brace.
+    fn ops(&mut self) -> AsmResult<()> {
+        loop {
+            match self.peek() {
+                Some(t) if t.text == "}" => return Ok(()),
+                Some(t) if t.text.len() > 1 && t.text.ends_with(':') && parse_info(&t.text).is_none() => {
+                    let label = t.text[..t.text.len() - 1].to_string();
+                    let info = t.info;
+                    if self.labels.insert(label.clone(), self.ops.len()).is_some() {
+                        return error(&info, &format!("duplicate label {}", label));
+                    }
+                    self.pos += 1;
+                }
+                Some(_) => self.op()?,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    fn op(&mut self) -> AsmResult<()> {
+        let (mut info, mut name) = self.word("an instruction")?;
+        if name.chars().all(|c| c.is_ascii_digit()) {
+            let t = self.word("an instruction")?;
+            info = t.0;
+            name = t.1;
+        }
+        if let Some(i) = parse_info(&name) {
+            info = i;
+            name = self.word("an instruction")?.1;
+        }
+
+        let op = match name.as_str() {
+            "LET" => CodeOP::LET(self.symbol()?),
+            "LD" => CodeOP::LD(self.symbol()?),
+            "SET" => CodeOP::SET(self.symbol()?),
+            "DEF" => CodeOP::DEF(self.symbol()?),
+            "LDC" => {
+                let (i, s) = self.word("a constant")?;
+                CodeOP::LDC(parse_const(&i, &s)?)
+            }
+            "ARGS" => CodeOP::ARGS(self.number()?),
+            "READ" => CodeOP::READ(self.number()?),
+            "VECTOR" => CodeOP::VECTOR(self.number()?),
+            "JMP" => {
+                let target = self.target()?;
+                self.ops.push((info, Op::Jmp(target)));
+                return Ok(());
+            }
+            "JMPF" => {
+                let target = self.target()?;
+                self.ops.push((info, Op::Jmpf(target)));
+                return Ok(());
+            }
+            "CLOSURE" => {
+                self.expect("(")?;
+                let mut names = Vec::new();
+                while self.peek().map(|t| t.text != ")").unwrap_or(false) {
+                    names.push(Symbol::intern(&self.word("a parameter")?.1));
+                }
+                self.expect(")")?;
+                let names = Rc::new(names.into_boxed_slice());
+
+                let brace = match self.peek() {
+                    Some(t) if t.text == "{" => t.info,
+                    _ => {
+                        let target = self.target()?;
+                        self.ops.push((info, Op::Closure(names, target)));
+                        return Ok(());
+                    }
+                };
+                self.pos += 1;
+
+                let at = self.ops.len();
+                self.ops.push((info, Op::Closure(names.clone(), Target::Offset(0))));
+                self.ops()?;
+                if self.peek().is_none() {
+                    return error(&brace, "unclosed '{'");
+                }
+                self.pos += 1;
+                let n = self.ops.len() - at - 1;
+                self.ops[at].1 = Op::Closure(names, Target::Offset(n));
+                return Ok(());
+            }
+            _ => {
+                match SIMPLE_OPS.iter().find(|op| format!("{:?}", op) == name) {
+                    Some(op) => op.clone(),
+                    None => return error(&info, &format!("unknown instruction '{}'", name)),
+                }
+            }
+        };
+        self.ops.push((info, Op::Plain(op)));
+        Ok(())
+    }
+
+    // turns labels into offsets from the next instruction
+    fn offset(&self, at: usize, target: &Target) -> AsmResult<usize> {
+        match target {
+            Target::Offset(n) => Ok(*n),
+            Target::Label(info, label) => {
+                match self.labels.get(label) {
+                    Some(&i) if i > at => Ok(i - at - 1),
+                    Some(_) => error(info, &format!("label {} is not after the jump", label)),
+                    None => error(info, &format!("undefined label {}", label)),
+                }
+            }
+        }
+    }
+}
+
+pub fn assemble(src: &str) -> Result<Code, Box<dyn Error>> {
+    let mut asm = Assembler {
+        tokens: tokenize(src),
+        pos: 0,
+        ops: Vec::new(),
+        labels: HashMap::new(),
+    };
+
+    asm.ops()?;
+    if let Some(t) = asm.peek() {
+        return error(&t.info, "unexpected '}'");
+    }
+
+    let mut code = Vec::with_capacity(asm.ops.len());
+    for (i, &(info, ref op)) in asm.ops.iter().enumerate() {
+        let op = match op {
+            Op::Plain(op) => op.clone(),
+            Op::Closure(names, target) => CodeOP::CLOSURE(names.clone(), asm.offset(i, target)?),
+            Op::Jmp(target) => CodeOP::JMP(asm.offset(i, target)?),
+            Op::Jmpf(target) => CodeOP::JMPF(asm.offset(i, target)?),
+        };
+        code.push(CodeOPInfo { info, op });
+    }
+
+    if let Err(e) = verify(&code) {
+        return error(&code[e.at].info, &e.msg);
+    }
+    Ok(Rc::new(code.into_boxed_slice()))
+}
@@ -0,0 +1,60 @@
+use data::Code;
+use snapshot::{Encoder, Decoder, Node};
+use verify::verify;
+
+use std::error::Error;
+
+// On-disk format for compiled programs, written by `secd compile`.
+//
+// The layout is the snapshot node table (see snapshot.rs) holding the code
+// and its constants, followed by a reference to the code and an FNV-1a
+// checksum of everything before it. Decoded code must also pass verify, as
+// the checksum only catches accidental damage.
+
+const MAGIC: &[u8; 8] = b"SECDCODE";
+pub const VERSION: u32 = 3;
+
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |h, &b| (h ^ u32::from(b)).wrapping_mul(0x0100_0193))
+}
+
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub fn encode(code: &Code) -> Vec<u8> {
+    let mut e = Encoder::new();
+    e.add(Node::Code(code.clone()));
+    e.header(MAGIC, VERSION);
+    e.nodes();
+    e.u32(e.id(&Node::Code(code.clone())));
+    let sum = checksum(&e.out);
+    e.u32(sum);
+    e.out
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Code, Box<dyn Error>> {
+    let natives = |_: &str| None;
+    let mut d = Decoder::new(bytes, "bytecode", &natives);
+    d.header(MAGIC, VERSION)?;
+
+    if bytes.len() < 16 {
+        return d.error("unexpected end of data");
+    }
+    let (body, sum) = bytes.split_at(bytes.len() - 4);
+    let mut b = [0; 4];
+    b.copy_from_slice(sum);
+    if checksum(body) != u32::from_le_bytes(b) {
+        return d.error("checksum mismatch, file is corrupt");
+    }
+
+    let mut d = Decoder::new(body, "bytecode", &natives);
+    d.header(MAGIC, VERSION)?;
+    d.nodes()?;
+    let code = d.code_ref()?;
+    d.finish()?;
+    if let Err(e) = verify(&code) {
+        return d.error(&format!("instruction {}: {}", e.at, e.msg));
+    }
+    Ok(code)
+}
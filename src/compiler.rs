@@ -1,471 +1,403 @@
-use data::{AST, SExpr, Lisp, Code, CodeOPInfo, CodeOP, Info};
+use data::{AST, Code, CodeOPInfo, CodeOP, Info};
+use ir::{Expr, ExprKind, Binder, Var, Lowerer, Diagnostic};
+use symbol::Symbol;
 
 use std::rc::Rc;
 use std::error::Error;
+use std::mem;
+use std::collections::{HashMap, HashSet};
 
 pub struct Compiler {
     pub code: Vec<CodeOPInfo>,
-    letrec_id_list: Vec<String>,
+    lowerer: Lowerer,
+    known: Vec<Known>,
+    // runtime names of the binders of the form being compiled
+    names: HashMap<u32, Symbol>,
+    fresh: usize,
 }
 
-type CompilerResult = Result<(), Box<Error>>;
-
-macro_rules! destruct_ {
-    ($e: expr, ()) => (
-        assert!($e.next().is_none())
-    );
-    ($e: expr, ($arg: ident, $($args: ident, )*)) => (
-        let $arg = $e.next().unwrap();
-        destruct_!($e, ($($args, )*))
-    );
-    ($e: expr, ($arg: ident $(, $args: ident)*)) => (
-        let $arg = $e.next().unwrap();
-        destruct_!($e, ($($args, )*))
-    );
+// a let-bound lambda whose calls are compiled in place
+struct Known {
+    binder: Binder,
+    params: Vec<Binder>,
+    body: Expr,
 }
 
-macro_rules! destruct {
-    ($e: expr, $($rest: tt)*) => (
-        let mut iter = $e.into_iter();
-        destruct_!(iter, $($rest)*)
-    )
+// let-bound lambdas with bodies up to this many nodes are inlined
+const INLINE_SIZE: usize = 24;
+
+type CompilerResult = Result<(), Box<dyn Error>>;
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Compiler {
     pub fn new() -> Self {
         Compiler {
             code: Vec::new(),
-            letrec_id_list: Vec::new(),
+            lowerer: Lowerer::new(),
+            known: Vec::new(),
+            names: HashMap::new(),
+            fresh: 0,
         }
     }
 
-    fn error(&self, info: &Info, msg: &str) -> CompilerResult {
-        Err(From::from(format!("{}:{}:compile error: {}", info[0], info[1], msg)))
-    }
-
-    pub fn compile(&mut self, ast: AST) -> Result<Code, Box<Error>> {
+    pub fn compile(&mut self, ast: AST) -> Result<Code, Box<dyn Error>> {
+        self.code.clear();
         self.compile_(ast)?;
-        Ok(Rc::new(self.code.clone().into_boxed_slice()))
+        Ok(Rc::new(mem::take(&mut self.code).into_boxed_slice()))
     }
 
     pub fn compile_(&mut self, ast: AST) -> CompilerResult {
-        let info = ast.info;
-        match ast.sexpr {
-            SExpr::Int(n) => self.compile_int(info, n),
-            SExpr::Atom(id) => self.compile_atom(info, id),
-            SExpr::List(mut ls) => {
-                if ls.is_empty() {
-                    self.compile_nil(info)
-                } else {
-                    let fun = ls.drain(0..1).next().unwrap();
-                    let args = ls;
-                    let info = fun.info;
-                    match fun.sexpr {
-                        SExpr::Int(_) => self.error(&info, "apply unexpect int"),
-                        SExpr::Atom(id) => {
-                            match id.as_str() {
-                                "lambda" => self.compile_lambda(info, args),
-                                "let" => self.compile_let(info, args),
-                                "letrec" => self.compile_letrec(info, args),
-                                "puts" => self.compile_puts(info, args),
-                                "if" => self.compile_if(info, args),
-                                "eq" => self.compile_eq(info, args),
-                                "+" => self.compile_add(info, args),
-                                "-" => self.compile_sub(info, args),
-                                "cons" => self.compile_cons(info, args),
-                                "car" => self.compile_car(info, args),
-                                "cdr" => self.compile_cdr(info, args),
-                                _ => {
-                                    self.compile_apply(info,
-                                                       AST {
-                                                           sexpr: SExpr::Atom(id),
-                                                           info: info,
-                                                       },
-                                                       args)
-                                }
-                            }
-                        }
-                        ex @ SExpr::List(_) => {
-                            self.compile_apply(info,
-                                               AST {
-                                                   sexpr: ex,
-                                                   info: info,
-                                               },
-                                               args)
-                        }
-                    }
-                }
-            }
-        }
+        // fresh names only need to be unique within a form, and reusing them
+        // keeps the symbol table from growing with every form compiled
+        self.fresh = 0;
+        self.names.clear();
+        let expr = self.lower(ast)?;
+        self.compile_expr(expr);
+        Ok(())
     }
 
-    fn compile_int(&mut self, info: Info, n: i32) -> CompilerResult {
-        self.code
-            .push(CodeOPInfo {
-                      info: info,
-                      op: CodeOP::LDC(Rc::new(Lisp::Int(n))),
-                  });
-        Ok(())
+    pub fn declare(&mut self, name: Symbol) {
+        self.lowerer.declare(name);
     }
 
-    fn compile_atom(&mut self, info: Info, id: String) -> CompilerResult {
-        match id.as_str() {
-            "nil" => {
-                self.code
-                    .push(CodeOPInfo {
-                              info: info,
-                              op: CodeOP::LDC(Rc::new(Lisp::Nil)),
-                          });
-            }
+    pub fn set_globals<I: IntoIterator<Item = Symbol>>(&mut self, names: I) {
+        self.lowerer.set_globals(names);
+    }
 
-            "true" => {
-                self.code
-                    .push(CodeOPInfo {
-                              info: info,
-                              op: CodeOP::LDC(Rc::new(Lisp::True)),
-                          });
-            }
+    // warnings about shadowed and unused bindings in the code compiled so far
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        self.lowerer.take_diagnostics()
+    }
 
-            "false" => {
-                self.code
-                    .push(CodeOPInfo {
-                              info: info,
-                              op: CodeOP::LDC(Rc::new(Lisp::False)),
-                          });
-            }
+    // the core form of ast, with calls inlined where possible
+    pub fn lower(&mut self, ast: AST) -> Result<Expr, Box<dyn Error>> {
+        let expr = self.lowerer.lower(ast)?;
+        Ok(self.inline(expr))
+    }
 
-            _ => {
-                self.code
-                    .push(CodeOPInfo {
-                              info: info,
-                              op: CodeOP::LD(id.clone()),
-                          });
-            }
-        }
+    fn emit(&mut self, info: Info, op: CodeOP) {
+        self.code.push(CodeOPInfo { info, op });
+    }
 
-        Ok(())
+    // points the jump or closure at `at` to the end of the code so far
+    fn patch(&mut self, at: usize) {
+        let end = self.code.len() - at - 1;
+        match self.code[at].op {
+            CodeOP::CLOSURE(_, ref mut n) |
+            CodeOP::JMP(ref mut n) |
+            CodeOP::JMPF(ref mut n) => *n = end,
+            _ => unreachable!(),
+        }
     }
 
-    fn compile_nil(&mut self, info: Info) -> CompilerResult {
-        self.code
-            .push(CodeOPInfo {
-                      info: info,
-                      op: CodeOP::LDC(Rc::new(Lisp::Nil)),
-                  });
-        Ok(())
+    // Let bindings go into the env of the current frame, and a letrec call
+    // sees its caller's env, so two binders with the same name could see each
+    // other's values at run time. Each binder gets a name of its own instead;
+    // `'` never appears in a parsed identifier.
+    fn bind(&mut self, b: Binder) -> Symbol {
+        self.fresh += 1;
+        let name = Symbol::intern(&format!("{}'{}", b.name, self.fresh));
+        self.names.insert(b.id, name);
+        name
     }
 
-    fn compile_lambda(&mut self, info: Info, ls: Vec<AST>) -> CompilerResult {
-        if ls.len() != 2 {
-            return self.error(&info, "lambda syntax");
+    fn var_name(&self, var: Var) -> Symbol {
+        match var {
+            Var::Bound(b) => self.names.get(&b.id).cloned().unwrap_or(b.name),
+            Var::Free(name) => name,
         }
+    }
 
-        destruct!(ls, (arg, body));
-
-        let mut args: Vec<String> = Vec::new();
-        match arg.sexpr {
-            SExpr::Atom(a) => {
-                args.push(a);
+    pub fn compile_expr(&mut self, expr: Expr) {
+        let info = expr.info;
+        match expr.kind {
+            ExprKind::Const(val) => self.emit(info, CodeOP::LDC(val)),
+            ExprKind::Var(var) => {
+                let name = self.var_name(var);
+                self.emit(info, CodeOP::LD(name));
+            }
+            ExprKind::Lambda(params, body) => {
+                let names: Vec<Symbol> = params.iter().map(|&b| self.bind(b)).collect();
+                let closure = self.code.len();
+                self.emit(info, CodeOP::CLOSURE(Rc::new(names.into_boxed_slice()), 0));
+                self.compile_expr(*body);
+                self.emit(info, CodeOP::RET);
+                self.patch(closure);
+            }
+            ExprKind::App(fun, args) => {
+                let nargs = args.len();
+                for arg in args {
+                    self.compile_expr(arg);
+                }
+                self.emit(info, CodeOP::ARGS(nargs));
+
+                // a letrec function finds itself in the caller's environment
+                let op = match fun.kind {
+                    ExprKind::Var(Var::Bound(b)) if b.rec => CodeOP::RAP,
+                    _ => CodeOP::AP,
+                };
+                self.compile_expr(*fun);
+                self.emit(info, op);
+            }
+            ExprKind::If(cond, then, else_) => {
+                self.compile_expr(*cond);
+                let jmpf = self.code.len();
+                self.emit(info, CodeOP::JMPF(0));
+
+                let then_info = then.info;
+                self.compile_expr(*then);
+                let jmp = self.code.len();
+                self.emit(then_info, CodeOP::JMP(0));
+                self.patch(jmpf);
+
+                self.compile_expr(*else_);
+                self.patch(jmp);
             }
+            ExprKind::Let(binder, expr, body) => {
+                self.compile_expr(*expr);
+                let name = self.bind(binder);
+                self.emit(info, CodeOP::LET(name));
+                self.compile_expr(*body);
+            }
+            ExprKind::LetRec(binder, expr, body) => {
+                // the expression already refers to the binder
+                let name = self.bind(binder);
+                self.compile_expr(*expr);
+                self.emit(info, CodeOP::LET(name));
+                self.compile_expr(*body);
+            }
+            ExprKind::Define(name, expr) => {
+                self.compile_expr(*expr);
+                self.emit(info, CodeOP::DEF(name));
+            }
+            ExprKind::Set(var, expr) => {
+                self.compile_expr(*expr);
+                let name = self.var_name(var);
+                self.emit(info, CodeOP::SET(name));
+            }
+            ExprKind::PrimOp(op, args) => {
+                for arg in args {
+                    self.compile_expr(arg);
+                }
+                self.emit(info, op);
+            }
+        }
+    }
 
-            SExpr::List(aa) => {
-                for ast in aa {
-                    match ast.sexpr {
-                        SExpr::Atom(a) => {
-                            args.push(a);
+    // Replaces applications of lambda literals, and calls of small let-bound
+    // lambdas, with the body of the lambda.
+    fn inline(&mut self, expr: Expr) -> Expr {
+        let info = expr.info;
+        let kind = match expr.kind {
+            ExprKind::Lambda(params, body) => ExprKind::Lambda(params, Box::new(self.inline(*body))),
+            ExprKind::App(fun, args) => {
+                let args: Vec<Expr> = args.into_iter().map(|e| self.inline(e)).collect();
+                let fun = self.inline(*fun);
+                let fun_info = fun.info;
+                match fun.kind {
+                    ExprKind::Lambda(params, body) => {
+                        if params.len() == args.len() && can_inline(&body) {
+                            return self.beta(info, &params, *body, args);
                         }
-
-                        _ => {
-                            return self.error(&info, "lambda args");
+                        let fun = Expr::new(fun_info, ExprKind::Lambda(params, body));
+                        ExprKind::App(Box::new(fun), args)
+                    }
+                    ExprKind::Var(Var::Bound(b)) => {
+                        let known = self.known
+                            .iter()
+                            .rev()
+                            .find(|k| k.binder == b && k.params.len() == args.len())
+                            .map(|k| (k.params.clone(), k.body.clone()));
+                        match known {
+                            Some((params, body)) => return self.beta(info, &params, body, args),
+                            None => {
+                                let fun = Expr::new(fun_info, ExprKind::Var(Var::Bound(b)));
+                                ExprKind::App(Box::new(fun), args)
+                            }
                         }
                     }
+                    kind => ExprKind::App(Box::new(Expr::new(fun_info, kind)), args),
                 }
             }
-
-            _ => {
-                return self.error(&arg.info, "lambda args");
+            ExprKind::If(cond, then, else_) => {
+                ExprKind::If(Box::new(self.inline(*cond)),
+                             Box::new(self.inline(*then)),
+                             Box::new(self.inline(*else_)))
             }
-        }
-
-        let mut body_compiler = Compiler::new();
-        body_compiler.letrec_id_list = self.letrec_id_list.clone();
-        body_compiler.compile_(body)?;
-        body_compiler.code
-            .push(CodeOPInfo {
-                      info: info,
-                      op: CodeOP::RET,
-                  });
-
-        self.code
-            .push(CodeOPInfo {
-                      info: info,
-                      op: CodeOP::LDF(Rc::new(args.into_boxed_slice()),
-                                      Rc::new(body_compiler.code.into_boxed_slice())),
-                  });
-
-        Ok(())
-    }
-
-    fn compile_let(&mut self, info: Info, ls: Vec<AST>) -> CompilerResult {
-        if ls.len() != 3 {
-            return self.error(&info, "let syntax");
-        }
-
-        destruct!(ls, (var, expr, body));
-
-        let id = match var.sexpr {
-            SExpr::Atom(id) => id,
-            _ => return self.error(&info, "let bind id sytax"),
+            ExprKind::Let(binder, expr, body) => {
+                let expr = self.inline(*expr);
+                let body = match inlinable(binder, &expr, &body) {
+                    Some(known) => {
+                        self.known.push(known);
+                        let body = self.inline(*body);
+                        self.known.pop();
+                        body
+                    }
+                    None => self.inline(*body),
+                };
+                ExprKind::Let(binder, Box::new(expr), Box::new(body))
+            }
+            ExprKind::LetRec(binder, expr, body) => {
+                ExprKind::LetRec(binder, Box::new(self.inline(*expr)), Box::new(self.inline(*body)))
+            }
+            ExprKind::Define(name, expr) => ExprKind::Define(name, Box::new(self.inline(*expr))),
+            ExprKind::Set(var, expr) => ExprKind::Set(var, Box::new(self.inline(*expr))),
+            ExprKind::PrimOp(op, args) => {
+                ExprKind::PrimOp(op, args.into_iter().map(|e| self.inline(e)).collect())
+            }
+            kind => kind,
         };
-
-        self.letrec_id_list.retain(|a| *a != id);
-
-        self.compile_(expr)?;
-        self.code
-            .push(CodeOPInfo {
-                      info: info,
-                      op: CodeOP::LET(id),
-                  });
-
-        self.compile_(body)?;
-
-        Ok(())
+        Expr::new(info, kind)
     }
 
-    fn compile_letrec(&mut self, info: Info, ls: Vec<AST>) -> CompilerResult {
-        if ls.len() != 3 {
-            return self.error(&info, "let syntax");
+    // ((lambda (x ...) body) arg ...) without a closure or a call. The body
+    // may be inlined more than once, so the parameters and every name it binds
+    // get fresh binders.
+    fn beta(&mut self, info: Info, params: &[Binder], body: Expr, args: Vec<Expr>) -> Expr {
+        let mut names = HashMap::new();
+        let params: Vec<Binder> = params.iter().map(|&p| self.rebind(p, &mut names)).collect();
+        let mut body = self.rename(body, &mut names);
+        for (param, arg) in params.into_iter().zip(args).rev() {
+            body = Expr::new(info, ExprKind::Let(param, Box::new(arg), Box::new(body)));
         }
-
-        destruct!(ls, (var, expr, body));
-
-        let id = match var.sexpr {
-            SExpr::Atom(id) => id,
-            _ => return self.error(&info, "let bind id sytax"),
-        };
-
-        self.letrec_id_list.push(id.clone());
-
-        self.compile_(expr)?;
-        self.code
-            .push(CodeOPInfo {
-                      info: info,
-                      op: CodeOP::LET(id),
-                  });
-        self.compile_(body)?;
-
-        Ok(())
+        body
     }
 
-    fn compile_puts(&mut self, info: Info, ls: Vec<AST>) -> CompilerResult {
-        if ls.len() != 1 {
-            return self.error(&info, "puts syntax");
-        }
-
-        destruct!(ls, (expr));
-
-        self.compile_(expr)?;
-        self.code
-            .push(CodeOPInfo {
-                      info: info,
-                      op: CodeOP::PUTS,
-                  });
-        Ok(())
+    fn rebind(&mut self, b: Binder, names: &mut HashMap<u32, Binder>) -> Binder {
+        let fresh = self.lowerer.binder(b.name, b.rec, b.info);
+        names.insert(b.id, fresh);
+        fresh
     }
 
-
-    fn compile_apply(&mut self, info: Info, lambda: AST, ls: Vec<AST>) -> CompilerResult {
-        let args = ls;
-        let nargs = args.len();
-        for arg in args.into_iter() {
-            self.compile_(arg)?;
-        }
-        self.code
-            .push(CodeOPInfo {
-                      info: info,
-                      op: CodeOP::ARGS(nargs),
-                  });
-        let (is_atom, id) = match lambda.sexpr {
-            SExpr::Atom(ref id) => (true, Some(id.clone())),
-            _ => (false, None),
+    fn rename(&mut self, expr: Expr, names: &mut HashMap<u32, Binder>) -> Expr {
+        let rename_var = |var: Var, names: &HashMap<u32, Binder>| match var {
+            Var::Bound(b) => Var::Bound(names.get(&b.id).cloned().unwrap_or(b)),
+            var => var,
         };
-
-        self.compile_(lambda)?;
-
-        match (is_atom, id) {
-            (true, Some(id)) => {
-                if self.letrec_id_list.iter().any(|a| a == &id) {
-                    self.code
-                        .push(CodeOPInfo {
-                                  info: info,
-                                  op: CodeOP::RAP,
-                              });
-                } else {
-                    self.code
-                        .push(CodeOPInfo {
-                                  info: info,
-                                  op: CodeOP::AP,
-                              });
-                }
+        let kind = match expr.kind {
+            ExprKind::Var(var) => ExprKind::Var(rename_var(var, names)),
+            ExprKind::Lambda(params, body) => {
+                let params = params.into_iter().map(|p| self.rebind(p, names)).collect();
+                ExprKind::Lambda(params, Box::new(self.rename(*body, names)))
             }
-
-            _ => {
-                self.code
-                    .push(CodeOPInfo {
-                              info: info,
-                              op: CodeOP::AP,
-                          });
+            ExprKind::App(fun, args) => {
+                let args = args.into_iter().map(|e| self.rename(e, names)).collect();
+                ExprKind::App(Box::new(self.rename(*fun, names)), args)
             }
-        }
-
-        Ok(())
-    }
-
-    fn compile_if(&mut self, info: Info, ls: Vec<AST>) -> CompilerResult {
-        if ls.len() != 3 {
-            return self.error(&info, "if syntax");
-        }
-
-        destruct!(ls, (cond, then, else_));
-
-        self.compile_(cond)?;
-
-        let mut tc = Compiler::new();
-        tc.letrec_id_list = self.letrec_id_list.clone();
-
-        let then_info = then.info.clone();
-        tc.compile_(then)?;
-        tc.code
-            .push(CodeOPInfo {
-                      info: then_info,
-                      op: CodeOP::JOIN,
-                  });
-
-        let mut fc = Compiler::new();
-        fc.letrec_id_list = self.letrec_id_list.clone();
-
-        let else_info = else_.info.clone();
-        fc.compile_(else_)?;
-        fc.code
-            .push(CodeOPInfo {
-                      info: else_info,
-                      op: CodeOP::JOIN,
-                  });
-
-        self.code
-            .push(CodeOPInfo {
-                      info: info,
-                      op: CodeOP::SEL(Rc::new(tc.code.into_boxed_slice()),
-                                      Rc::new(fc.code.into_boxed_slice())),
-                  });
-
-        Ok(())
-    }
-
-
-    fn compile_eq(&mut self, info: Info, ls: Vec<AST>) -> CompilerResult {
-        if ls.len() != 2 {
-            return self.error(&info, "eq syntax");
-        }
-
-        destruct!(ls, (l, r));
-
-        self.compile_(l)?;
-        self.compile_(r)?;
-        self.code
-            .push(CodeOPInfo {
-                      info: info,
-                      op: CodeOP::EQ,
-                  });
-
-        Ok(())
-    }
-
-    fn compile_add(&mut self, info: Info, ls: Vec<AST>) -> CompilerResult {
-        if ls.len() != 2 {
-            return self.error(&info, "add syntax");
-        }
-
-        destruct!(ls, (l, r));
-
-        self.compile_(l)?;
-        self.compile_(r)?;
-        self.code
-            .push(CodeOPInfo {
-                      info: info,
-                      op: CodeOP::ADD,
-                  });
-
-        Ok(())
-    }
-
-    fn compile_sub(&mut self, info: Info, ls: Vec<AST>) -> CompilerResult {
-        if ls.len() != 2 {
-            return self.error(&info, "sub syntax");
-        }
-
-        destruct!(ls, (l, r));
-
-        self.compile_(l)?;
-        self.compile_(r)?;
-        self.code
-            .push(CodeOPInfo {
-                      info: info,
-                      op: CodeOP::SUB,
-                  });
-
-        Ok(())
+            ExprKind::If(cond, then, else_) => {
+                ExprKind::If(Box::new(self.rename(*cond, names)),
+                             Box::new(self.rename(*then, names)),
+                             Box::new(self.rename(*else_, names)))
+            }
+            ExprKind::Let(binder, expr, body) => {
+                let binder = self.rebind(binder, names);
+                ExprKind::Let(binder,
+                              Box::new(self.rename(*expr, names)),
+                              Box::new(self.rename(*body, names)))
+            }
+            ExprKind::LetRec(binder, expr, body) => {
+                let binder = self.rebind(binder, names);
+                ExprKind::LetRec(binder,
+                                 Box::new(self.rename(*expr, names)),
+                                 Box::new(self.rename(*body, names)))
+            }
+            ExprKind::Define(name, expr) => ExprKind::Define(name, Box::new(self.rename(*expr, names))),
+            ExprKind::Set(var, expr) => {
+                ExprKind::Set(rename_var(var, names), Box::new(self.rename(*expr, names)))
+            }
+            ExprKind::PrimOp(op, args) => {
+                ExprKind::PrimOp(op, args.into_iter().map(|e| self.rename(e, names)).collect())
+            }
+            kind => kind,
+        };
+        Expr::new(expr.info, kind)
     }
+}
 
-    fn compile_cons(&mut self, info: Info, ls: Vec<AST>) -> CompilerResult {
-        if ls.len() != 2 {
-            return self.error(&info, "cons syntax");
-        }
-
-        destruct!(ls, (l, r));
-
-        self.compile_(l)?;
-        self.compile_(r)?;
-        self.code
-            .push(CodeOPInfo {
-                      info: info,
-                      op: CodeOP::CONS,
-                  });
+// Bodies that eval, define or refer to a letrec function stay real calls:
+// those can see the caller's environment by name.
+fn can_inline(body: &Expr) -> bool {
+    let mut ok = true;
+    body.walk(&mut |e| match e.kind {
+                        ExprKind::PrimOp(CodeOP::EVAL, _) |
+                        ExprKind::Define(..) => ok = false,
+                        ExprKind::Var(Var::Bound(b)) if b.rec => ok = false,
+                        _ => {}
+                    });
+    ok
+}
 
-        Ok(())
+// `(let f (lambda ...) body)` can have calls of f inlined in body when the
+// lambda is small, does not mention f, and nothing in body binds f or a
+// name the lambda refers to from outside, or evals
+fn inlinable(binder: Binder, expr: &Expr, body: &Expr) -> Option<Known> {
+    let (params, fbody) = match expr.kind {
+        ExprKind::Lambda(ref params, ref fbody) => (params, &**fbody),
+        _ => return None,
+    };
+    if fbody.size() > INLINE_SIZE || !can_inline(fbody) {
+        return None;
     }
 
-    fn compile_car(&mut self, info: Info, ls: Vec<AST>) -> CompilerResult {
-        if ls.len() != 1 {
-            return self.error(&info, "car syntax");
-        }
-
-        destruct!(ls, (expr));
-
-        self.compile_(expr)?;
-        self.code
-            .push(CodeOPInfo {
-                      info: info,
-                      op: CodeOP::CAR,
+    let mut inner: HashSet<u32> = params.iter().map(|b| b.id).collect();
+    fbody.walk(&mut |e| {
+                        for b in binders(e) {
+                            inner.insert(b.id);
+                        }
+                    });
+    let mut refs = HashSet::new();
+    fbody.walk(&mut |e| match e.kind {
+                        ExprKind::Var(var) |
+                        ExprKind::Set(var, _) => {
+                            match var {
+                                Var::Bound(b) if inner.contains(&b.id) => {}
+                                var => {
+                                    refs.insert(var.name());
+                                }
+                            }
+                        }
+                        _ => {}
+                    });
+
+    let mut bound = HashSet::new();
+    let mut evals = false;
+    body.walk(&mut |e| {
+                      for b in binders(e) {
+                          bound.insert(b.name);
+                      }
+                      match e.kind {
+                          ExprKind::Define(name, _) => {
+                              bound.insert(name);
+                          }
+                          ExprKind::Set(var, _) => {
+                              bound.insert(var.name());
+                          }
+                          ExprKind::PrimOp(CodeOP::EVAL, _) => evals = true,
+                          _ => {}
+                      }
                   });
 
-        Ok(())
+    if evals || refs.contains(&binder.name) || bound.contains(&binder.name) ||
+       refs.iter().any(|name| bound.contains(name)) {
+        return None;
     }
 
-    fn compile_cdr(&mut self, info: Info, ls: Vec<AST>) -> CompilerResult {
-        if ls.len() != 1 {
-            return self.error(&info, "cdr syntax");
-        }
-
-        destruct!(ls, (expr));
-
-        self.compile_(expr)?;
-        self.code
-            .push(CodeOPInfo {
-                      info: info,
-                      op: CodeOP::CDR,
-                  });
+    Some(Known {
+             binder,
+             params: params.clone(),
+             body: fbody.clone(),
+         })
+}
 
-        Ok(())
+fn binders(expr: &Expr) -> Vec<Binder> {
+    match expr.kind {
+        ExprKind::Lambda(ref params, _) => params.clone(),
+        ExprKind::Let(b, ..) | ExprKind::LetRec(b, ..) => vec![b],
+        _ => Vec::new(),
     }
 }
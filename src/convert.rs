@@ -0,0 +1,275 @@
+use data::{Lisp, Value};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::error::Error;
+
+// Conversions between Rust values and Lisp data.
+//
+// Strings are lists of chars, Vec and tuples are proper lists, None is nil
+// and HashMap is a hash table. Only integer types that always fit in an
+// i32 implement ToLisp; FromLisp is range checked for all of them.
+//
+// Some(x) is just x, so Lisp code sees an optional value as the value or nil.
+// A Some whose value is itself nil, such as Some(()), Some(String::new()) or
+// Some(Vec::new()), therefore comes back as None.
+
+pub trait ToLisp {
+    fn to_lisp(&self) -> Value;
+}
+
+pub trait FromLisp: Sized {
+    fn from_lisp(val: &Value) -> Result<Self, Box<dyn Error>>;
+}
+
+fn expected<T>(what: &str, val: &Value) -> Result<T, Box<dyn Error>> {
+    Err(From::from(format!("expected {}, got {}", what, val)))
+}
+
+impl ToLisp for Value {
+    fn to_lisp(&self) -> Value {
+        self.clone()
+    }
+}
+
+impl FromLisp for Value {
+    fn from_lisp(val: &Value) -> Result<Self, Box<dyn Error>> {
+        Ok(val.clone())
+    }
+}
+
+macro_rules! to_lisp_int {
+    ($($t: ty),*) => ($(
+        impl ToLisp for $t {
+            fn to_lisp(&self) -> Value {
+                Value::Int(i32::from(*self))
+            }
+        }
+    )*)
+}
+
+macro_rules! from_lisp_int {
+    ($($t: ty),*) => ($(
+        impl FromLisp for $t {
+            fn from_lisp(val: &Value) -> Result<Self, Box<dyn Error>> {
+                match *val {
+                    Value::Int(n) => {
+                        <$t as ::std::convert::TryFrom<i32>>::try_from(n).map_err(|_| {
+                            From::from(format!("{} out of range for {}", n, stringify!($t)))
+                        })
+                    }
+                    _ => expected("int", val),
+                }
+            }
+        }
+    )*)
+}
+
+to_lisp_int!(i8, i16, i32, u8, u16);
+from_lisp_int!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl ToLisp for bool {
+    fn to_lisp(&self) -> Value {
+        Value::bool(*self)
+    }
+}
+
+impl FromLisp for bool {
+    fn from_lisp(val: &Value) -> Result<Self, Box<dyn Error>> {
+        match *val {
+            Value::True => Ok(true),
+            Value::False => Ok(false),
+            _ => expected("bool", val),
+        }
+    }
+}
+
+impl ToLisp for () {
+    fn to_lisp(&self) -> Value {
+        Value::Nil
+    }
+}
+
+impl FromLisp for () {
+    fn from_lisp(val: &Value) -> Result<Self, Box<dyn Error>> {
+        match *val {
+            Value::Nil => Ok(()),
+            _ => expected("nil", val),
+        }
+    }
+}
+
+impl ToLisp for char {
+    fn to_lisp(&self) -> Value {
+        Value::Char(*self)
+    }
+}
+
+impl FromLisp for char {
+    fn from_lisp(val: &Value) -> Result<Self, Box<dyn Error>> {
+        match *val {
+            Value::Char(c) => Ok(c),
+            _ => expected("char", val),
+        }
+    }
+}
+
+impl ToLisp for str {
+    fn to_lisp(&self) -> Value {
+        Value::from_vec(self.chars().map(|c| c.to_lisp()).collect())
+    }
+}
+
+impl ToLisp for String {
+    fn to_lisp(&self) -> Value {
+        self.as_str().to_lisp()
+    }
+}
+
+impl FromLisp for String {
+    fn from_lisp(val: &Value) -> Result<Self, Box<dyn Error>> {
+        match *val {
+            Value::Symbol(id) => Ok(id.to_string()),
+            _ => Vec::<char>::from_lisp(val).map(|cs| cs.into_iter().collect()),
+        }
+    }
+}
+
+impl<T: ToLisp> ToLisp for Vec<T> {
+    fn to_lisp(&self) -> Value {
+        Value::from_vec(self.iter().map(ToLisp::to_lisp).collect())
+    }
+}
+
+impl<T: FromLisp> FromLisp for Vec<T> {
+    fn from_lisp(val: &Value) -> Result<Self, Box<dyn Error>> {
+        let vals = match val.as_lisp() {
+            Some(Lisp::Vector(vals)) => vals.borrow().clone(),
+            _ => {
+                match val.list_to_vec() {
+                    Some(vals) => vals,
+                    None => return expected("list", val),
+                }
+            }
+        };
+
+        vals.iter().map(T::from_lisp).collect()
+    }
+}
+
+// not a round trip when T can be nil, see above
+impl<T: ToLisp> ToLisp for Option<T> {
+    fn to_lisp(&self) -> Value {
+        match *self {
+            Some(ref val) => val.to_lisp(),
+            None => Value::Nil,
+        }
+    }
+}
+
+impl<T: FromLisp> FromLisp for Option<T> {
+    fn from_lisp(val: &Value) -> Result<Self, Box<dyn Error>> {
+        match *val {
+            Value::Nil => Ok(None),
+            _ => T::from_lisp(val).map(Some),
+        }
+    }
+}
+
+impl<K: ToLisp, V: ToLisp, S> ToLisp for HashMap<K, V, S> {
+    #[allow(clippy::mutable_key_type)]
+    fn to_lisp(&self) -> Value {
+        let table = self.iter().map(|(k, v)| (k.to_lisp(), v.to_lisp())).collect();
+        Value::new(Lisp::HashTable(RefCell::new(table)))
+    }
+}
+
+impl<K: FromLisp + Eq + Hash, V: FromLisp> FromLisp for HashMap<K, V> {
+    fn from_lisp(val: &Value) -> Result<Self, Box<dyn Error>> {
+        match val.as_lisp() {
+            Some(Lisp::HashTable(table)) => {
+                table.borrow()
+                    .iter()
+                    .map(|(k, v)| Ok((K::from_lisp(k)?, V::from_lisp(v)?)))
+                    .collect()
+            }
+            _ => expected("hash table", val),
+        }
+    }
+}
+
+macro_rules! lisp_tuple {
+    ($n: tt, $($t: ident $i: tt),*) => (
+        impl<$($t: ToLisp),*> ToLisp for ($($t,)*) {
+            fn to_lisp(&self) -> Value {
+                Value::from_vec(vec![$(self.$i.to_lisp()),*])
+            }
+        }
+
+        impl<$($t: FromLisp),*> FromLisp for ($($t,)*) {
+            fn from_lisp(val: &Value) -> Result<Self, Box<dyn Error>> {
+                match val.list_to_vec() {
+                    Some(ref vals) if vals.len() == $n => Ok(($($t::from_lisp(&vals[$i])?,)*)),
+                    _ => expected(concat!("list of length ", $n), val),
+                }
+            }
+        }
+    )
+}
+
+lisp_tuple!(1, A 0);
+lisp_tuple!(2, A 0, B 1);
+lisp_tuple!(3, A 0, B 1, C 2);
+lisp_tuple!(4, A 0, B 1, C 2, D 3);
+lisp_tuple!(5, A 0, B 1, C 2, D 3, E 4);
+lisp_tuple!(6, A 0, B 1, C 2, D 3, E 4, F 5);
+
+/// Implements `ToLisp` and `FromLisp` for a struct, represented as a vector
+/// of its fields in the listed order.
+///
+/// ```
+/// #[macro_use] extern crate secd;
+/// use secd::{ToLisp, FromLisp};
+///
+/// #[derive(Debug, PartialEq)]
+/// struct Point { x: i32, y: i32 }
+/// lisp_struct!(Point { x, y });
+///
+/// # fn main() {
+/// let p = Point { x: 1, y: 2 };
+/// assert_eq!(Point::from_lisp(&p.to_lisp()).unwrap(), p);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! lisp_struct {
+    ($name: ident { $($field: ident),* $(,)* }) => (
+        impl $crate::ToLisp for $name {
+            fn to_lisp(&self) -> $crate::Value {
+                let vals = vec![$($crate::ToLisp::to_lisp(&self.$field)),*];
+                $crate::Value::new($crate::Lisp::Vector(::std::cell::RefCell::new(vals)))
+            }
+        }
+
+        impl $crate::FromLisp for $name {
+            fn from_lisp(val: &$crate::Value) -> Result<Self, Box<dyn (::std::error::Error)>> {
+                let fields = [$(stringify!($field)),*];
+                match val.as_lisp() {
+                    Some($crate::Lisp::Vector(vals)) if vals.borrow().len() == fields.len() => {
+                        let vals = vals.borrow();
+                        let mut vals = vals.iter();
+                        Ok($name {
+                            $($field: $crate::FromLisp::from_lisp(vals.next().unwrap())?),*
+                        })
+                    }
+                    _ => {
+                        Err(From::from(format!("expected {} {:?}, got {}",
+                                               stringify!($name),
+                                               fields,
+                                               val)))
+                    }
+                }
+            }
+        }
+    )
+}
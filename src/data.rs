@@ -1,34 +1,61 @@
+use printer::{Printer, Mode, write_char};
+use output::Output;
+use limits::Limits;
+use symbol::{Symbol, BuildSymbolHasher};
+
 use std::fmt;
 use std::rc::Rc;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::error::Error;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug)]
 pub struct SECD {
     pub stack: Stack,
     pub code: (Code, CodePos),
     pub env: Env,
     pub dump: Dump,
+    pub globals: Env,
+    pub input: String,
+    pub output: Output,
+    pub limits: Limits,
+    pub executed: u64,
+    pub allocated: u64,
+    // dump frames and stack values of the callers suspended by call, which
+    // count toward the limits, and how many calls are in progress
+    pub suspended: (usize, usize),
+    pub calls: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Status {
+    Paused,
+    Finished(Value),
 }
 
-pub type Stack = Vec<Rc<Lisp>>;
+pub type Stack = Vec<Value>;
 pub type Code = Rc<Box<[CodeOPInfo]>>;
 pub type CodePos = usize;
-pub type Env = HashMap<String, Rc<Lisp>>;
+pub type Env = HashMap<Symbol, Rc<RefCell<Value>>, BuildSymbolHasher>;
 pub type Dump = Vec<DumpOP>;
 
 pub type Info = [usize; 2];
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct AST {
     pub info: Info,
     pub sexpr: SExpr,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum SExpr {
     Atom(String),
     Int(i32),
+    Char(char),
     List(Vec<AST>),
+    Vector(Vec<AST>),
 }
 
 #[derive(Debug, Clone)]
@@ -39,40 +66,140 @@ pub struct CodeOPInfo {
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum CodeOP {
-    LET(String),
-    LD(String),
-    LDC(Rc<Lisp>),
-    LDF(Rc<Box<[String]>>, Code),
-    SEL(Code, Code),
-    JOIN,
+    LET(Symbol),
+    LD(Symbol),
+    SET(Symbol),
+    DEF(Symbol),
+    LDC(Value),
+    // Jump offsets are relative to the next instruction and only go forward.
+    // A closure's body follows its CLOSURE instruction, which skips over it.
+    CLOSURE(Rc<Box<[Symbol]>>, usize),
+    JMP(usize),
+    JMPF(usize),
     RET,
     AP,
     RAP,
     ARGS(usize),
     PUTS,
+    WRITE,
+    READ(usize),
+    EVAL,
     EQ,
     ADD,
     SUB,
     CONS,
     CAR,
     CDR,
+    SETCAR,
+    SETCDR,
+    BOX,
+    UNBOX,
+    SETBOX,
+    VECTOR(usize),
+    MKVECTOR,
+    VECTORREF,
+    VECTORSET,
+    VECTORLEN,
+    MKHASH,
+    HASHREF,
+    HASHSET,
+    HASHHAS,
+    HASHREMOVE,
+    HASHKEYS,
+    HASHCOUNT,
+    CHAR2INT,
+    INT2CHAR,
+    CHAREQ,
+    CHARLT,
 }
 
+// instructions without operands; snapshots and bytecode files encode them by
+// their index here, so only append
+pub const SIMPLE_OPS: &[CodeOP] = &[CodeOP::RET,
+                                    CodeOP::AP,
+                                    CodeOP::RAP,
+                                    CodeOP::PUTS,
+                                    CodeOP::WRITE,
+                                    CodeOP::EVAL,
+                                    CodeOP::EQ,
+                                    CodeOP::ADD,
+                                    CodeOP::SUB,
+                                    CodeOP::CONS,
+                                    CodeOP::CAR,
+                                    CodeOP::CDR,
+                                    CodeOP::SETCAR,
+                                    CodeOP::SETCDR,
+                                    CodeOP::BOX,
+                                    CodeOP::UNBOX,
+                                    CodeOP::SETBOX,
+                                    CodeOP::MKVECTOR,
+                                    CodeOP::VECTORREF,
+                                    CodeOP::VECTORSET,
+                                    CodeOP::VECTORLEN,
+                                    CodeOP::MKHASH,
+                                    CodeOP::HASHREF,
+                                    CodeOP::HASHSET,
+                                    CodeOP::HASHHAS,
+                                    CodeOP::HASHREMOVE,
+                                    CodeOP::HASHKEYS,
+                                    CodeOP::HASHCOUNT,
+                                    CodeOP::CHAR2INT,
+                                    CodeOP::INT2CHAR,
+                                    CodeOP::CHAREQ,
+                                    CodeOP::CHARLT];
+
+// A call frame: the height of the caller's stack, which the callee works on
+// top of, and the caller's env and continuation.
 #[derive(Debug, PartialEq)]
 pub enum DumpOP {
-    DumpAP(Stack, Env, (Code, CodePos)),
-    DumpSEL((Code, CodePos)),
+    DumpAP(usize, Env, (Code, CodePos)),
 }
 
-#[derive(Debug, PartialEq)]
-pub enum Lisp {
+// Immediates are stored inline, so pushing a number or a boolean never
+// allocates; everything else lives behind a reference count.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Value {
     Nil,
     False,
     True,
     Int(i32),
-    List(Vec<Rc<Lisp>>),
-    Closure(Rc<Box<[String]>>, Code, Env),
-    Cons(Rc<Lisp>, Rc<Lisp>),
+    Char(char),
+    Symbol(Symbol),
+    Ref(Rc<Lisp>),
+}
+
+// heap objects
+#[derive(Debug)]
+pub enum Lisp {
+    List(Vec<Value>),
+    Closure(Rc<Box<[Symbol]>>, Code, CodePos, Env),
+    Native(Native),
+    Cons(RefCell<Value>, RefCell<Value>),
+    Box(RefCell<Value>),
+    Vector(RefCell<Vec<Value>>),
+    HashTable(RefCell<HashMap<Value, Value>>),
+}
+
+pub type NativeFn = Rc<dyn Fn(&mut SECD, &[Value]) -> Result<Value, Box<dyn Error>>>;
+
+// a host function registered through Interpreter::register_fn
+#[derive(Clone)]
+pub struct Native {
+    pub name: String,
+    pub arity: usize,
+    pub fun: NativeFn,
+}
+
+impl fmt::Debug for Native {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Native({:?}, {})", self.name, self.arity)
+    }
+}
+
+impl PartialEq for Native {
+    fn eq(&self, a: &Native) -> bool {
+        Rc::ptr_eq(&self.fun, &a.fun)
+    }
 }
 
 impl fmt::Display for AST {
@@ -80,7 +207,11 @@ impl fmt::Display for AST {
         match self.sexpr {
             SExpr::Atom(ref id) => write!(f, "{}", id),
             SExpr::Int(ref n) => write!(f, "{}", n),
-            SExpr::List(ref list) => {
+            SExpr::Char(c) => write_char(f, c),
+            SExpr::List(ref list) | SExpr::Vector(ref list) => {
+                if let SExpr::Vector(_) = self.sexpr {
+                    write!(f, "#").unwrap();
+                }
                 write!(f, "(").unwrap();
                 for i in 0..list.len() {
                     if i == list.len() - 1 {
@@ -95,22 +226,307 @@ impl fmt::Display for AST {
     }
 }
 
+pub fn char_from_name(name: &str) -> Option<char> {
+    match name {
+        "space" => Some(' '),
+        "newline" => Some('\n'),
+        "tab" => Some('\t'),
+        _ => {
+            let mut cs = name.chars();
+            match (cs.next(), cs.next()) {
+                (Some(c), None) => Some(c),
+                _ => None,
+            }
+        }
+    }
+}
+
 impl PartialEq for CodeOPInfo {
     fn eq(&self, a: &CodeOPInfo) -> bool {
         self.op == a.op
     }
 }
 
-impl fmt::Display for Lisp {
+impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Printer::new(Mode::Display).print(f, self)
+    }
+}
+
+impl Value {
+    pub fn new(lisp: Lisp) -> Value {
+        Value::Ref(Rc::new(lisp))
+    }
+
+    pub fn bool(b: bool) -> Value {
+        if b { Value::True } else { Value::False }
+    }
+
+    pub fn cons(car: Value, cdr: Value) -> Value {
+        Value::new(Lisp::Cons(RefCell::new(car), RefCell::new(cdr)))
+    }
+
+    pub fn from_vec(vals: Vec<Value>) -> Value {
+        vals.into_iter().rev().fold(Value::Nil, |cdr, car| Value::cons(car, cdr))
+    }
+
+    // the heap object behind a reference, None for immediates
+    pub fn as_lisp(&self) -> Option<&Lisp> {
         match self {
-            &Lisp::Nil => write!(f, "nil"),
-            &Lisp::True => write!(f, "true"),
-            &Lisp::False => write!(f, "false"),
-            &Lisp::Int(n) => write!(f, "{}", n),
-            &Lisp::Cons(ref car, ref cdr) => write!(f, "(cons {} {})", car, cdr),
-            &Lisp::List(ref ls) => write!(f, "(list {:?})", ls),
-            &Lisp::Closure(ref args, _, _) => write!(f, "(lambda {:?} Code)", args),
+            Value::Ref(l) => Some(l),
+            _ => None,
+        }
+    }
+
+    // elements of a proper list, None for anything else, cyclic lists included
+    pub fn list_to_vec(&self) -> Option<Vec<Value>> {
+        let mut vals = Vec::new();
+        let mut rest = self.clone();
+        // follows rest at half speed; catching up with it means a cycle
+        let mut slow = self.clone();
+        loop {
+            let next = match rest {
+                Value::Nil => return Some(vals),
+                Value::Ref(ref l) => {
+                    match **l {
+                        Lisp::Cons(ref car, ref cdr) => {
+                            vals.push(car.borrow().clone());
+                            cdr.borrow().clone()
+                        }
+                        _ => return None,
+                    }
+                }
+                _ => return None,
+            };
+            rest = next;
+            if vals.len() % 2 == 0 {
+                slow = match slow {
+                    Value::Ref(ref l) => {
+                        match **l {
+                            Lisp::Cons(_, ref cdr) => cdr.borrow().clone(),
+                            _ => unreachable!(),
+                        }
+                    }
+                    _ => unreachable!(),
+                };
+                if let (Value::Ref(a), Value::Ref(b)) = (&rest, &slow) {
+                    if Rc::ptr_eq(a, b) {
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Lisp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Printer::new(Mode::Display).print_lisp(f, self)
+    }
+}
+
+// Objects are unlinked iteratively, moving the children of every object
+// dropped with its last reference onto a work stack, so dropping deep nesting
+// of any kind cannot overflow the native stack.
+impl Drop for Lisp {
+    fn drop(&mut self) {
+        // immediates are left where they are, so most drops never allocate
+        fn push(work: &mut Vec<Value>, val: Value) {
+            if let Value::Ref(_) = val {
+                work.push(val);
+            }
+        }
+
+        fn take(cell: &mut RefCell<Value>) -> Value {
+            mem::replace(cell.get_mut(), Value::Nil)
+        }
+
+        fn children(l: &mut Lisp, work: &mut Vec<Value>) {
+            match l {
+                Lisp::List(vals) => {
+                    for val in vals.drain(..) {
+                        push(work, val);
+                    }
+                }
+                Lisp::Closure(_, _, _, env) => {
+                    for (_, cell) in env.drain() {
+                        if let Ok(cell) = Rc::try_unwrap(cell) {
+                            push(work, cell.into_inner());
+                        }
+                    }
+                }
+                Lisp::Native(_) => {}
+                Lisp::Cons(car, cdr) => {
+                    push(work, take(car));
+                    push(work, take(cdr));
+                }
+                Lisp::Box(val) => push(work, take(val)),
+                Lisp::Vector(vals) => {
+                    for val in vals.get_mut().drain(..) {
+                        push(work, val);
+                    }
+                }
+                Lisp::HashTable(table) => {
+                    for (k, v) in table.get_mut().drain() {
+                        push(work, k);
+                        push(work, v);
+                    }
+                }
+            }
+        }
+
+        let mut work = Vec::new();
+        children(self, &mut work);
+        while let Some(val) = work.pop() {
+            if let Value::Ref(l) = val {
+                if let Ok(mut l) = Rc::try_unwrap(l) {
+                    children(&mut l, &mut work);
+                }
+            }
+        }
+    }
+}
+
+// Structural equality, walking both values with a worklist rather than
+// recursion so long lists cannot overflow the native stack. A pair of objects
+// already being compared counts as equal, which ends the walk on cyclic data.
+impl PartialEq for Lisp {
+    fn eq(&self, other: &Lisp) -> bool {
+        let mut seen = HashSet::new();
+        let mut work = Vec::new();
+        if !shallow_eq(self, other, &mut work) {
+            return false;
+        }
+        while let Some((a, b)) = work.pop() {
+            match (&a, &b) {
+                (Value::Ref(x), Value::Ref(y)) => {
+                    if Rc::ptr_eq(x, y) || !seen.insert((Rc::as_ptr(x), Rc::as_ptr(y))) {
+                        continue;
+                    }
+                    if !shallow_eq(x, y, &mut work) {
+                        return false;
+                    }
+                }
+                _ => {
+                    if a != b {
+                        return false;
+                    }
+                }
+            }
+        }
+        true
+    }
+}
+
+// compares everything but the values a and b hold, which go on work
+fn shallow_eq(a: &Lisp, b: &Lisp, work: &mut Vec<(Value, Value)>) -> bool {
+    fn push(work: &mut Vec<(Value, Value)>, a: &RefCell<Value>, b: &RefCell<Value>) {
+        work.push((a.borrow().clone(), b.borrow().clone()));
+    }
+
+    match (a, b) {
+        (Lisp::List(a), Lisp::List(b)) => {
+            a.len() == b.len() && {
+                work.extend(a.iter().cloned().zip(b.iter().cloned()));
+                true
+            }
+        }
+        (Lisp::Closure(n1, c1, p1, e1), Lisp::Closure(n2, c2, p2, e2)) => {
+            if n1 != n2 || c1 != c2 || p1 != p2 || e1.len() != e2.len() {
+                return false;
+            }
+            for (name, cell) in e1 {
+                match e2.get(name) {
+                    Some(other) => push(work, cell, other),
+                    None => return false,
+                }
+            }
+            true
+        }
+        (Lisp::Native(a), Lisp::Native(b)) => a == b,
+        (Lisp::Cons(car1, cdr1), Lisp::Cons(car2, cdr2)) => {
+            push(work, car1, car2);
+            push(work, cdr1, cdr2);
+            true
+        }
+        (Lisp::Box(a), Lisp::Box(b)) => {
+            push(work, a, b);
+            true
+        }
+        (Lisp::Vector(a), Lisp::Vector(b)) => {
+            let (a, b) = (a.borrow(), b.borrow());
+            a.len() == b.len() && {
+                work.extend(a.iter().cloned().zip(b.iter().cloned()));
+                true
+            }
+        }
+        (Lisp::HashTable(a), Lisp::HashTable(b)) => {
+            let (a, b) = (a.borrow(), b.borrow());
+            if a.len() != b.len() {
+                return false;
+            }
+            for (key, val) in a.iter() {
+                match b.get(key) {
+                    Some(other) => work.push((val.clone(), other.clone())),
+                    None => return false,
+                }
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+impl Eq for Lisp {}
+
+// objects hashed per value; equal values unfold alike, so hashing the same
+// number of objects in the same order keeps even cyclic ones consistent
+const HASH_OBJECTS: usize = 64;
+
+// Consistent with PartialEq: closures hash only their parameter names and
+// hash tables only their size, so equal values always hash alike. Mutating a
+// value used as a hash table key is not supported.
+impl Hash for Lisp {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut work: Vec<Value> = Vec::new();
+        let mut lisp = self;
+        let mut n = 0;
+        let mut rc;
+        loop {
+            mem::discriminant(lisp).hash(state);
+            match lisp {
+                Lisp::List(ls) => {
+                    ls.len().hash(state);
+                    work.extend(ls.iter().rev().cloned());
+                }
+                Lisp::Closure(args, ..) => args.hash(state),
+                Lisp::Native(native) => native.name.hash(state),
+                Lisp::Cons(car, cdr) => {
+                    work.push(cdr.borrow().clone());
+                    work.push(car.borrow().clone());
+                }
+                Lisp::Box(val) => work.push(val.borrow().clone()),
+                Lisp::Vector(vals) => {
+                    let vals = vals.borrow();
+                    vals.len().hash(state);
+                    work.extend(vals.iter().rev().cloned());
+                }
+                Lisp::HashTable(table) => table.borrow().len().hash(state),
+            }
+
+            n += 1;
+            loop {
+                match work.pop() {
+                    Some(Value::Ref(l)) if n < HASH_OBJECTS => {
+                        rc = l;
+                        lisp = &rc;
+                        break;
+                    }
+                    Some(Value::Ref(_)) => {}
+                    Some(val) => val.hash(state),
+                    None => return,
+                }
+            }
         }
     }
 }
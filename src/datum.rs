@@ -0,0 +1,81 @@
+use data::{AST, SExpr, Lisp, Value, Info};
+use symbol::Symbol;
+
+use std::cell::RefCell;
+use std::error::Error;
+
+// conversion between source syntax and Lisp data, used by read and eval
+
+pub fn ast_to_lisp(ast: &AST) -> Value {
+    match ast.sexpr {
+        SExpr::Int(n) => Value::Int(n),
+        SExpr::Char(c) => Value::Char(c),
+        SExpr::Atom(ref id) => {
+            match id.as_str() {
+                "nil" => Value::Nil,
+                "true" => Value::True,
+                "false" => Value::False,
+                _ => Value::Symbol(Symbol::intern(id)),
+            }
+        }
+        SExpr::List(ref ls) => Value::from_vec(ls.iter().map(ast_to_lisp).collect()),
+        SExpr::Vector(ref ls) => {
+            Value::new(Lisp::Vector(RefCell::new(ls.iter().map(ast_to_lisp).collect())))
+        }
+    }
+}
+
+// allocations ast_to_lisp makes for ast: a cons per list element, and a
+// vector and its slots
+pub fn allocations(ast: &AST) -> u64 {
+    match ast.sexpr {
+        SExpr::List(ref ls) => ls.len() as u64 + ls.iter().map(allocations).sum::<u64>(),
+        SExpr::Vector(ref ls) => ls.len() as u64 + 1 + ls.iter().map(allocations).sum::<u64>(),
+        _ => 0,
+    }
+}
+
+pub fn lisp_to_ast(val: &Value, info: Info) -> Result<AST, Box<dyn Error>> {
+    to_ast(val, info, &mut Vec::new())
+}
+
+// within holds the vectors and lists val is part of, which val may not
+// contain again
+fn to_ast(val: &Value, info: Info, within: &mut Vec<*const Lisp>) -> Result<AST, Box<dyn Error>> {
+    let sexpr = match val {
+        Value::Nil => SExpr::Atom("nil".to_string()),
+        Value::True => SExpr::Atom("true".to_string()),
+        Value::False => SExpr::Atom("false".to_string()),
+        Value::Int(n) => SExpr::Int(*n),
+        Value::Char(c) => SExpr::Char(*c),
+        Value::Symbol(id) => SExpr::Atom(id.to_string()),
+        Value::Ref(lisp) => {
+            let vals = match **lisp {
+                Lisp::Vector(ref vals) => vals.borrow().clone(),
+                Lisp::Cons(..) => {
+                    match val.list_to_vec() {
+                        Some(vals) => vals,
+                        None => return Err(From::from(format!("can not eval dotted list {}", val))),
+                    }
+                }
+                _ => return Err(From::from(format!("can not eval {}", val))),
+            };
+            let p: *const Lisp = &**lisp;
+            if within.contains(&p) {
+                return Err(From::from(format!("can not eval self-referential data {}", val)));
+            }
+            within.push(p);
+            let mut ls = Vec::new();
+            for val in &vals {
+                ls.push(to_ast(val, info, within)?);
+            }
+            within.pop();
+            match **lisp {
+                Lisp::Vector(_) => SExpr::Vector(ls),
+                _ => SExpr::List(ls),
+            }
+        }
+    };
+
+    Ok(AST { info, sexpr })
+}
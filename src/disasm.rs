@@ -0,0 +1,49 @@
+use data::{Code, CodeOP};
+use printer;
+
+use std::fmt::Write;
+
+// Human readable listing of compiled code. Each instruction is printed with
+// its index and source line:column, closure bodies are indented, and jumps
+// note the index they continue at, e.g.
+//
+//        0  1:1     CLOSURE (x) 2  ; -> 3
+//        1  1:12      LD x
+//        2  1:12      RET
+pub fn disassemble(code: &Code) -> String {
+    let mut out = String::new();
+    // end index of every closure body we are in
+    let mut bodies: Vec<usize> = Vec::new();
+    for (i, op) in code.iter().enumerate() {
+        while bodies.last() == Some(&i) {
+            bodies.pop();
+        }
+
+        let text = match op.op {
+            CodeOP::LET(ref id) => format!("LET {}", id),
+            CodeOP::LD(ref id) => format!("LD {}", id),
+            CodeOP::SET(ref id) => format!("SET {}", id),
+            CodeOP::DEF(ref id) => format!("DEF {}", id),
+            CodeOP::LDC(ref lisp) => format!("LDC {}", printer::write(lisp)),
+            CodeOP::CLOSURE(ref names, n) => {
+                format!("CLOSURE ({}) {}  ; -> {}", printer::join(names), n, i + 1 + n)
+            }
+            CodeOP::JMP(n) => format!("JMP {}  ; -> {}", n, i + 1 + n),
+            CodeOP::JMPF(n) => format!("JMPF {}  ; -> {}", n, i + 1 + n),
+            CodeOP::ARGS(n) => format!("ARGS {}", n),
+            CodeOP::READ(n) => format!("READ {}", n),
+            CodeOP::VECTOR(n) => format!("VECTOR {}", n),
+            ref op => format!("{:?}", op),
+        };
+
+        let info = format!("{}:{}", op.info[0], op.info[1]);
+        writeln!(out, "{:6}  {:<7} {:indent$}{}", i, info, "", text, indent = 2 * bodies.len())
+            .unwrap();
+
+        if let CodeOP::CLOSURE(_, n) = op.op {
+            bodies.push(i + 1 + n);
+        }
+    }
+
+    out
+}
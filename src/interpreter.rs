@@ -0,0 +1,127 @@
+use data::{SECD, Lisp, Value, Env, Code, Native};
+use parser::Parser;
+use compiler::Compiler;
+use ir::Diagnostic;
+use convert::ToLisp;
+use output::Output;
+use limits::Limits;
+use symbol::Symbol;
+
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::error::Error;
+use std::mem;
+
+// A persistent session: globals created by `define` or from the host survive
+// across eval_str calls, and each form is compiled knowing the current ones.
+pub struct Interpreter {
+    globals: Env,
+    compiler: Compiler,
+    output: Output,
+    limits: Limits,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Interpreter {
+            globals: Env::default(),
+            compiler: Compiler::new(),
+            output: Output::stdout(),
+            limits: Limits::default(),
+        }
+    }
+
+    pub fn set_output(&mut self, output: Output) {
+        self.output = output;
+    }
+
+    // applied afresh to every eval_str, eval_code and call; the instructions
+    // and allocations of all the forms of one eval_str count together
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn register_fn<F>(&mut self, name: &str, arity: usize, fun: F)
+        where F: Fn(&[Value]) -> Result<Value, Box<dyn Error>> + 'static
+    {
+        self.register_fn_with_vm(name, arity, move |_, args| fun(args));
+    }
+
+    // like register_fn, but the function also gets the running machine so it
+    // can call back into Lisp with SECD::call
+    pub fn register_fn_with_vm<F>(&mut self, name: &str, arity: usize, fun: F)
+        where F: Fn(&mut SECD, &[Value]) -> Result<Value, Box<dyn Error>> + 'static
+    {
+        let native = Lisp::Native(Native {
+                                      name: name.to_string(),
+                                      arity,
+                                      fun: Rc::new(fun),
+                                  });
+        self.define(name, Value::new(native));
+    }
+
+    pub fn define<T: ToLisp>(&mut self, name: &str, val: T) {
+        self.globals.insert(Symbol::intern(name), Rc::new(RefCell::new(val.to_lisp())));
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<Value> {
+        self.globals.get(&Symbol::intern(name)).map(|cell| cell.borrow().clone())
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Value> {
+        self.globals.remove(&Symbol::intern(name)).map(|cell| cell.borrow().clone())
+    }
+
+    // evaluates every top-level expression in order, returning the last value
+    pub fn eval_str(&mut self, src: &str) -> Result<Value, Box<dyn Error>> {
+        let mut parser = Parser::new(&src.to_string());
+        let mut val = Value::Nil;
+        let mut used = (0, 0);
+        while let Some(ast) = parser.read()? {
+            self.compiler.set_globals(self.globals.keys().cloned());
+            let code = self.compiler.compile(ast)?;
+            val = self.with_machine(code, &mut used, |secd| secd.run())?;
+        }
+
+        Ok(val)
+    }
+
+    // warnings about the forms compiled by eval_str so far
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        self.compiler.take_diagnostics()
+    }
+
+    // runs code compiled ahead of time, e.g. loaded from a bytecode file
+    pub fn eval_code(&mut self, code: Code) -> Result<Value, Box<dyn Error>> {
+        self.with_machine(code, &mut (0, 0), |secd| secd.run())
+    }
+
+    pub fn call(&mut self, f: &Value, args: &[Value]) -> Result<Value, Box<dyn Error>> {
+        let code = Rc::new(Vec::new().into_boxed_slice());
+        self.with_machine(code, &mut (0, 0), |secd| secd.call(f, args))
+    }
+
+    // used holds the instructions executed and allocations made so far
+    // against the limits, and is updated by the run
+    fn with_machine<T, F>(&mut self, code: Code, used: &mut (u64, u64), f: F) -> T
+        where F: FnOnce(&mut SECD) -> T
+    {
+        let mut secd = SECD::new(code);
+        secd.globals = mem::take(&mut self.globals);
+        secd.output = mem::take(&mut self.output);
+        secd.limits = self.limits;
+        secd.executed = used.0;
+        secd.allocated = used.1;
+        let r = f(&mut secd);
+        self.globals = mem::take(&mut secd.globals);
+        self.output = mem::take(&mut secd.output);
+        *used = (secd.executed, secd.allocated);
+        r
+    }
+}
@@ -0,0 +1,453 @@
+use data::{AST, SExpr, Value, CodeOP, Info};
+use symbol::Symbol;
+
+use std::fmt;
+use std::mem;
+use std::error::Error;
+use std::collections::HashSet;
+
+// The core language the compiler works on. Lowering checks the syntax of
+// every form, desugars it into one of a handful of nodes, and resolves each
+// variable to the lambda, let or letrec binding it, or to a global. Names
+// that are neither are compile errors. Every node keeps the position of the
+// form it came from.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub info: Info,
+    pub kind: ExprKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Const(Value),
+    Var(Var),
+    Lambda(Vec<Binder>, Box<Expr>),
+    App(Box<Expr>, Vec<Expr>),
+    If(Box<Expr>, Box<Expr>, Box<Expr>),
+    Let(Binder, Box<Expr>, Box<Expr>),
+    LetRec(Binder, Box<Expr>, Box<Expr>),
+    Define(Symbol, Box<Expr>),
+    Set(Var, Box<Expr>),
+    // an operation that compiles to a single instruction after its arguments
+    PrimOp(CodeOP, Vec<Expr>),
+}
+
+// A name introduced by a lambda, let or letrec. Ids are unique among the
+// binders made by one Lowerer, so two bindings of the same name differ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Binder {
+    pub name: Symbol,
+    pub id: u32,
+    pub rec: bool,
+    pub info: Info,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Var {
+    Bound(Binder),
+    // a global or a native function
+    Free(Symbol),
+}
+
+// a warning found while lowering; names starting with `_` are never reported
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub info: Info,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:warning: {}", self.info[0], self.info[1], self.message)
+    }
+}
+
+impl Var {
+    pub fn name(&self) -> Symbol {
+        match *self {
+            Var::Bound(b) => b.name,
+            Var::Free(name) => name,
+        }
+    }
+}
+
+impl Expr {
+    pub fn new(info: Info, kind: ExprKind) -> Expr {
+        Expr { info, kind }
+    }
+
+    // the nodes directly below this one, in evaluation order
+    pub fn children(&self) -> Vec<&Expr> {
+        match self.kind {
+            ExprKind::Const(_) | ExprKind::Var(_) => Vec::new(),
+            ExprKind::Lambda(_, ref body) => vec![body],
+            ExprKind::App(ref fun, ref args) => {
+                let mut ls: Vec<&Expr> = args.iter().collect();
+                ls.push(fun);
+                ls
+            }
+            ExprKind::If(ref cond, ref then, ref else_) => vec![cond, then, else_],
+            ExprKind::Let(_, ref expr, ref body) |
+            ExprKind::LetRec(_, ref expr, ref body) => vec![expr, body],
+            ExprKind::Define(_, ref expr) |
+            ExprKind::Set(_, ref expr) => vec![expr],
+            ExprKind::PrimOp(_, ref args) => args.iter().collect(),
+        }
+    }
+
+    // calls f on this node and every node below it
+    pub fn walk<'a>(&'a self, f: &mut dyn FnMut(&'a Expr)) {
+        f(self);
+        for child in self.children() {
+            child.walk(f);
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        1 + self.children().iter().map(|e| e.size()).sum::<usize>()
+    }
+}
+
+type LowerResult<T> = Result<T, Box<dyn Error>>;
+
+macro_rules! destruct_ {
+    ($e: expr, ()) => (
+        assert!($e.next().is_none())
+    );
+    ($e: expr, ($arg: ident, $($args: ident, )*)) => (
+        let $arg = $e.next().unwrap();
+        destruct_!($e, ($($args, )*))
+    );
+    ($e: expr, ($arg: ident $(, $args: ident)*)) => (
+        let $arg = $e.next().unwrap();
+        destruct_!($e, ($($args, )*))
+    );
+}
+
+macro_rules! destruct {
+    ($e: expr, $($rest: tt)*) => (
+        let mut iter = $e.into_iter();
+        destruct_!(iter, $($rest)*)
+    )
+}
+
+fn error<T>(info: &Info, msg: &str) -> LowerResult<T> {
+    Err(From::from(format!("{}:{}:compile error: {}", info[0], info[1], msg)))
+}
+
+pub struct Lowerer {
+    scopes: Vec<Binder>,
+    next: u32,
+    globals: HashSet<Symbol>,
+    used: HashSet<u32>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Default for Lowerer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lowerer {
+    pub fn new() -> Self {
+        Lowerer {
+            scopes: Vec::new(),
+            next: 0,
+            globals: HashSet::new(),
+            used: HashSet::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    pub fn binder(&mut self, name: Symbol, rec: bool, info: Info) -> Binder {
+        self.next += 1;
+        Binder {
+            name,
+            id: self.next,
+            rec,
+            info,
+        }
+    }
+
+    // makes a global name known, as `define` does for the code after it
+    pub fn declare(&mut self, name: Symbol) {
+        self.globals.insert(name);
+    }
+
+    // replaces the known globals, e.g. with those of a running machine
+    pub fn set_globals<I: IntoIterator<Item = Symbol>>(&mut self, names: I) {
+        self.globals = names.into_iter().collect();
+    }
+
+    // the warnings found so far, by position
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        let mut diagnostics = mem::take(&mut self.diagnostics);
+        diagnostics.sort_by_key(|d| d.info);
+        diagnostics
+    }
+
+    pub fn lower(&mut self, ast: AST) -> LowerResult<Expr> {
+        let depth = self.scopes.len();
+        let r = self.lower_(ast);
+        self.scopes.truncate(depth);
+        r
+    }
+
+    fn warn(&mut self, info: Info, message: String) {
+        self.diagnostics.push(Diagnostic { info, message });
+    }
+
+    fn resolve(&mut self, id: &str, info: Info) -> LowerResult<Var> {
+        let name = Symbol::intern(id);
+        match self.scopes.iter().rev().find(|b| b.name == name) {
+            Some(&b) => {
+                self.used.insert(b.id);
+                Ok(Var::Bound(b))
+            }
+            None if self.globals.contains(&name) => Ok(Var::Free(name)),
+            None => error(&info, &format!("unbound variable '{}'", id)),
+        }
+    }
+
+    fn bind(&mut self, b: Binder) {
+        if self.scopes.iter().any(|s| s.name == b.name) && !b.name.as_str().starts_with('_') {
+            self.warn(b.info, format!("'{}' shadows an outer binding", b.name));
+        }
+        self.scopes.push(b);
+    }
+
+    // ends the scopes of the binders above depth
+    fn unbind(&mut self, depth: usize) {
+        for b in self.scopes.split_off(depth) {
+            if !self.used.contains(&b.id) && !b.name.as_str().starts_with('_') {
+                self.warn(b.info, format!("unused binding '{}'", b.name));
+            }
+        }
+    }
+
+    fn lower_(&mut self, ast: AST) -> LowerResult<Expr> {
+        let info = ast.info;
+        let kind = match ast.sexpr {
+            SExpr::Int(n) => ExprKind::Const(Value::Int(n)),
+            SExpr::Char(c) => ExprKind::Const(Value::Char(c)),
+            SExpr::Atom(id) => {
+                match id.as_str() {
+                    "nil" => ExprKind::Const(Value::Nil),
+                    "true" => ExprKind::Const(Value::True),
+                    "false" => ExprKind::Const(Value::False),
+                    _ => ExprKind::Var(self.resolve(&id, info)?),
+                }
+            }
+            SExpr::Vector(ls) => {
+                let n = ls.len();
+                self.prim(info, ls, n, "vector", CodeOP::VECTOR(n))?
+            }
+            SExpr::List(mut ls) => {
+                if ls.is_empty() {
+                    return Ok(Expr::new(info, ExprKind::Const(Value::Nil)));
+                }
+
+                let fun = ls.remove(0);
+                let args = ls;
+                let info = fun.info;
+                let kind = match fun.sexpr {
+                    SExpr::Int(_) => return error(&info, "apply unexpect int"),
+                    SExpr::Char(_) => return error(&info, "apply unexpect char"),
+                    SExpr::Vector(_) => return error(&info, "apply unexpect vector"),
+                    SExpr::Atom(id) => {
+                        match id.as_str() {
+                            "lambda" => self.lambda(info, args)?,
+                            "let" => self.let_(info, args, false)?,
+                            "letrec" => self.let_(info, args, true)?,
+                            "define" => self.define(info, args)?,
+                            "puts" => self.prim(info, args, 1, "puts", CodeOP::PUTS)?,
+                            "write" => self.prim(info, args, 1, "write", CodeOP::WRITE)?,
+                            "read" => {
+                                if args.len() > 1 {
+                                    return error(&info, "read syntax");
+                                }
+                                let n = args.len();
+                                self.prim(info, args, n, "read", CodeOP::READ(n))?
+                            }
+                            "eval" => self.prim(info, args, 1, "eval", CodeOP::EVAL)?,
+                            "if" => self.if_(info, args)?,
+                            "eq" => self.prim(info, args, 2, "eq", CodeOP::EQ)?,
+                            "+" => self.prim(info, args, 2, "add", CodeOP::ADD)?,
+                            "-" => self.prim(info, args, 2, "sub", CodeOP::SUB)?,
+                            "cons" => self.prim(info, args, 2, "cons", CodeOP::CONS)?,
+                            "car" => self.prim(info, args, 1, "car", CodeOP::CAR)?,
+                            "cdr" => self.prim(info, args, 1, "cdr", CodeOP::CDR)?,
+                            "set!" => self.set(info, args)?,
+                            "set-car!" => self.prim(info, args, 2, "set-car!", CodeOP::SETCAR)?,
+                            "set-cdr!" => self.prim(info, args, 2, "set-cdr!", CodeOP::SETCDR)?,
+                            "box" => self.prim(info, args, 1, "box", CodeOP::BOX)?,
+                            "unbox" => self.prim(info, args, 1, "unbox", CodeOP::UNBOX)?,
+                            "set-box!" => self.prim(info, args, 2, "set-box!", CodeOP::SETBOX)?,
+                            "vector" => {
+                                let n = args.len();
+                                self.prim(info, args, n, "vector", CodeOP::VECTOR(n))?
+                            }
+                            "make-vector" => self.prim(info, args, 2, "make-vector", CodeOP::MKVECTOR)?,
+                            "vector-ref" => self.prim(info, args, 2, "vector-ref", CodeOP::VECTORREF)?,
+                            "vector-set!" => self.prim(info, args, 3, "vector-set!", CodeOP::VECTORSET)?,
+                            "vector-length" => self.prim(info, args, 1, "vector-length", CodeOP::VECTORLEN)?,
+                            "make-hash-table" => self.prim(info, args, 0, "make-hash-table", CodeOP::MKHASH)?,
+                            "hash-ref" => self.prim(info, args, 2, "hash-ref", CodeOP::HASHREF)?,
+                            "hash-set!" => self.prim(info, args, 3, "hash-set!", CodeOP::HASHSET)?,
+                            "hash-has-key?" => self.prim(info, args, 2, "hash-has-key?", CodeOP::HASHHAS)?,
+                            "hash-remove!" => self.prim(info, args, 2, "hash-remove!", CodeOP::HASHREMOVE)?,
+                            "hash-keys" => self.prim(info, args, 1, "hash-keys", CodeOP::HASHKEYS)?,
+                            "hash-count" => self.prim(info, args, 1, "hash-count", CodeOP::HASHCOUNT)?,
+                            "char->integer" => self.prim(info, args, 1, "char->integer", CodeOP::CHAR2INT)?,
+                            "integer->char" => self.prim(info, args, 1, "integer->char", CodeOP::INT2CHAR)?,
+                            "char=?" => self.prim(info, args, 2, "char=?", CodeOP::CHAREQ)?,
+                            "char<?" => self.prim(info, args, 2, "char<?", CodeOP::CHARLT)?,
+                            _ => {
+                                let args = self.lower_all(args)?;
+                                let fun = Expr::new(info, ExprKind::Var(self.resolve(&id, info)?));
+                                ExprKind::App(Box::new(fun), args)
+                            }
+                        }
+                    }
+                    sexpr @ SExpr::List(_) => {
+                        // arguments are evaluated before the function
+                        let args = self.lower_all(args)?;
+                        let fun = self.lower_(AST { info, sexpr })?;
+                        ExprKind::App(Box::new(fun), args)
+                    }
+                };
+                return Ok(Expr::new(info, kind));
+            }
+        };
+        Ok(Expr::new(info, kind))
+    }
+
+    fn lower_all(&mut self, ls: Vec<AST>) -> LowerResult<Vec<Expr>> {
+        ls.into_iter().map(|ast| self.lower_(ast)).collect()
+    }
+
+    fn prim(&mut self,
+            info: Info,
+            ls: Vec<AST>,
+            arity: usize,
+            name: &str,
+            op: CodeOP)
+            -> LowerResult<ExprKind> {
+        if ls.len() != arity {
+            return error(&info, &format!("{} syntax", name));
+        }
+        Ok(ExprKind::PrimOp(op, self.lower_all(ls)?))
+    }
+
+    fn lambda(&mut self, info: Info, ls: Vec<AST>) -> LowerResult<ExprKind> {
+        if ls.len() != 2 {
+            return error(&info, "lambda syntax");
+        }
+
+        destruct!(ls, (arg, body));
+
+        let mut names = Vec::new();
+        match arg.sexpr {
+            SExpr::Atom(a) => names.push((Symbol::intern(&a), arg.info)),
+            SExpr::List(aa) => {
+                for ast in aa {
+                    match ast.sexpr {
+                        SExpr::Atom(a) => names.push((Symbol::intern(&a), ast.info)),
+                        _ => return error(&info, "lambda args"),
+                    }
+                }
+            }
+            _ => return error(&arg.info, "lambda args"),
+        }
+
+        let params: Vec<Binder> = names.into_iter()
+            .map(|(name, info)| self.binder(name, false, info))
+            .collect();
+        let depth = self.scopes.len();
+        for &param in &params {
+            self.bind(param);
+        }
+        let body = self.lower_(body)?;
+        self.unbind(depth);
+
+        Ok(ExprKind::Lambda(params, Box::new(body)))
+    }
+
+    // a letrec binding is already in scope in its own expression
+    fn let_(&mut self, info: Info, ls: Vec<AST>, rec: bool) -> LowerResult<ExprKind> {
+        if ls.len() != 3 {
+            return error(&info, "let syntax");
+        }
+
+        destruct!(ls, (var, expr, body));
+
+        let name = match var.sexpr {
+            SExpr::Atom(id) => Symbol::intern(&id),
+            _ => return error(&info, "let bind id sytax"),
+        };
+        let binder = self.binder(name, rec, var.info);
+
+        let depth = self.scopes.len();
+        if rec {
+            self.bind(binder);
+        }
+        let expr = self.lower_(expr)?;
+        if !rec {
+            self.bind(binder);
+        }
+        let body = self.lower_(body)?;
+        self.unbind(depth);
+
+        let (expr, body) = (Box::new(expr), Box::new(body));
+        Ok(if rec {
+               ExprKind::LetRec(binder, expr, body)
+           } else {
+               ExprKind::Let(binder, expr, body)
+           })
+    }
+
+    // a defined name is known from its own expression on, so it can recurse
+    fn define(&mut self, info: Info, ls: Vec<AST>) -> LowerResult<ExprKind> {
+        if ls.len() != 2 {
+            return error(&info, "define syntax");
+        }
+
+        destruct!(ls, (var, expr));
+
+        let name = match var.sexpr {
+            SExpr::Atom(id) => Symbol::intern(&id),
+            _ => return error(&info, "define id syntax"),
+        };
+        self.declare(name);
+        Ok(ExprKind::Define(name, Box::new(self.lower_(expr)?)))
+    }
+
+    fn set(&mut self, info: Info, ls: Vec<AST>) -> LowerResult<ExprKind> {
+        if ls.len() != 2 {
+            return error(&info, "set! syntax");
+        }
+
+        destruct!(ls, (var, expr));
+
+        let var = match var.sexpr {
+            SExpr::Atom(id) => self.resolve(&id, var.info)?,
+            _ => return error(&info, "set! id syntax"),
+        };
+        Ok(ExprKind::Set(var, Box::new(self.lower_(expr)?)))
+    }
+
+    fn if_(&mut self, info: Info, ls: Vec<AST>) -> LowerResult<ExprKind> {
+        if ls.len() != 3 {
+            return error(&info, "if syntax");
+        }
+
+        destruct!(ls, (cond, then, else_));
+
+        let cond = self.lower_(cond)?;
+        let then = self.lower_(then)?;
+        let else_ = self.lower_(else_)?;
+        Ok(ExprKind::If(Box::new(cond), Box::new(then), Box::new(else_)))
+    }
+}
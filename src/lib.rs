@@ -1,26 +1,99 @@
 pub mod data;
 pub mod parser;
+pub mod ir;
 pub mod compiler;
 pub mod vm;
+pub mod printer;
+pub mod datum;
+pub mod interpreter;
+pub mod convert;
+pub mod output;
+pub mod limits;
+pub mod snapshot;
+pub mod bytecode;
+pub mod verify;
+pub mod disasm;
+pub mod asm;
+pub mod symbol;
+pub mod optimizer;
+pub mod types;
 
-pub use data::{SECD, Lisp};
+pub use data::{SECD, Lisp, Value, Status};
 pub use parser::Parser;
 pub use compiler::Compiler;
+pub use interpreter::Interpreter;
+pub use convert::{ToLisp, FromLisp};
+pub use output::Output;
+pub use limits::{Limits, Limit, LimitExceeded};
+pub use disasm::disassemble;
+pub use asm::assemble;
+pub use symbol::Symbol;
+pub use ir::Diagnostic;
+pub use optimizer::optimize;
+pub use types::{Type, TypeChecker};
+
+use data::Code;
 
 use std::rc::Rc;
 use std::error::Error;
 use std::fs::File;
 use std::io::Read;
 
-pub fn run_lisp(s: &String) -> Result<Rc<Lisp>, Box<Error>> {
-    let ast = Parser::new(s).parse()?;
-    let code = Compiler::new().compile(ast)?;
-    SECD::new(code).run()
+pub fn run_lisp(s: &str) -> Result<Value, Box<dyn Error>> {
+    Interpreter::new().eval_str(s)
+}
+
+// Runs a source file, or a bytecode file written by `secd compile`.
+pub fn run_lisp_file(s: &String) -> Result<Value, Box<dyn Error>> {
+    let mut fh = File::open(s)?;
+    let mut bytes = Vec::new();
+    fh.read_to_end(&mut bytes)?;
+    if bytecode::is_bytecode(&bytes) {
+        return Interpreter::new().eval_code(bytecode::decode(&bytes)?);
+    }
+    run_lisp(&String::from_utf8(bytes)?)
 }
 
-pub fn run_lisp_file(s: &String) -> Result<Rc<Lisp>, Box<Error>> {
+// Loads the code of a bytecode file, or compiles a source file.
+pub fn load_code_file(s: &str) -> Result<Code, Box<dyn Error>> {
     let mut fh = File::open(s)?;
-    let mut src = String::new();
-    fh.read_to_string(&mut src)?;
-    run_lisp(&src)
+    let mut bytes = Vec::new();
+    fh.read_to_end(&mut bytes)?;
+    if bytecode::is_bytecode(&bytes) {
+        return bytecode::decode(&bytes);
+    }
+    compile_lisp(&String::from_utf8(bytes)?)
+}
+
+// Compiles every top-level form of a program into a single code block.
+pub fn compile_lisp(s: &str) -> Result<Code, Box<dyn Error>> {
+    Ok(compile_lisp_with_diagnostics(s)?.0)
+}
+
+// Like compile_lisp, also returning warnings about the program.
+pub fn compile_lisp_with_diagnostics(s: &str) -> Result<(Code, Vec<Diagnostic>), Box<dyn Error>> {
+    let mut parser = Parser::new(&s.to_string());
+    let mut compiler = Compiler::new();
+    let mut code = Vec::new();
+    while let Some(ast) = parser.read()? {
+        code.extend(compiler.compile(ast)?.iter().cloned());
+    }
+
+    if code.is_empty() {
+        return Err(From::from("empty program"));
+    }
+    Ok((Rc::new(code.into_boxed_slice()), compiler.take_diagnostics()))
+}
+
+// Infers the type of every top-level form of a program without running it,
+// returning the type of the last one.
+pub fn typecheck_lisp(s: &str) -> Result<Type, Box<dyn Error>> {
+    let mut parser = Parser::new(&s.to_string());
+    let mut lowerer = ir::Lowerer::new();
+    let mut checker = TypeChecker::new();
+    let mut ty = None;
+    while let Some(ast) = parser.read()? {
+        ty = Some(checker.typecheck(&lowerer.lower(ast)?)?);
+    }
+    ty.ok_or_else(|| From::from("empty program"))
 }
@@ -0,0 +1,57 @@
+use data::Info;
+
+use std::fmt;
+use std::error::Error;
+
+// Resource limits for running untrusted code; None means unlimited.
+// `allocations` counts what the machine allocates over the whole run: conses,
+// boxes, closures, hash tables and entries, vectors and argument lists and
+// their slots, env cells, data built by read and code compiled by eval. It
+// is not the live heap, so it also caps a long loop that frees what it
+// allocates. Values returned by native functions are not counted.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    pub instructions: Option<u64>,
+    pub dump_depth: Option<usize>,
+    pub stack_size: Option<usize>,
+    pub allocations: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Instructions,
+    DumpDepth,
+    StackSize,
+    Allocations,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LimitExceeded {
+    pub limit: Limit,
+    pub max: u64,
+    pub info: Info,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::Instructions => write!(f, "instructions"),
+            Limit::DumpDepth => write!(f, "dump depth"),
+            Limit::StackSize => write!(f, "stack size"),
+            Limit::Allocations => write!(f, "allocations"),
+        }
+    }
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "{}:{}:vm error: limit exceeded: {} ({})",
+               self.info[0],
+               self.info[1],
+               self.limit,
+               self.max)
+    }
+}
+
+impl Error for LimitExceeded {}
@@ -1,13 +1,96 @@
 extern crate secd;
 
 use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::error::Error;
+use std::process;
+
+use secd::data::Code;
+
+const USAGE: &str = "usage:
+  secd [-O] [--typecheck] <file>
+  secd [-O] [--typecheck] run <file>
+  secd [-O] [--typecheck] compile <file.lisp> -o <file.secdc>
+  secd [-O] [--typecheck] disasm <file>
+  secd [-O] asm <file.s>";
+
+#[derive(Clone, Copy)]
+struct Flags {
+    opt: bool,
+    typecheck: bool,
+}
+
+fn optimized(code: Code, flags: Flags) -> Code {
+    if flags.opt { secd::optimize(&code) } else { code }
+}
+
+// Compiles a source file, printing any warnings, or loads a bytecode file.
+// With --typecheck, a source file must also pass the type checker.
+fn load(file: &str, flags: Flags) -> Result<Code, Box<dyn Error>> {
+    let mut bytes = Vec::new();
+    File::open(file)?.read_to_end(&mut bytes)?;
+    if secd::bytecode::is_bytecode(&bytes) {
+        return secd::bytecode::decode(&bytes);
+    }
+
+    let src = String::from_utf8(bytes)?;
+    if flags.typecheck {
+        secd::typecheck_lisp(&src)?;
+    }
+    let (code, diagnostics) = secd::compile_lisp_with_diagnostics(&src)?;
+    for d in diagnostics {
+        eprintln!("{}", d);
+    }
+    Ok(code)
+}
+
+fn compile(src: &str, out: &str, flags: Flags) -> Result<(), Box<dyn Error>> {
+    let code = optimized(load(src, flags)?, flags);
+    File::create(out)?.write_all(&secd::bytecode::encode(&code))?;
+    Ok(())
+}
+
+fn disasm(file: &str, flags: Flags) -> Result<(), Box<dyn Error>> {
+    print!("{}", secd::disassemble(&optimized(load(file, flags)?, flags)));
+    Ok(())
+}
+
+fn asm(file: &str, flags: Flags) -> Result<(), Box<dyn Error>> {
+    let mut s = String::new();
+    File::open(file)?.read_to_string(&mut s)?;
+    let code = optimized(secd::assemble(&s)?, flags);
+    println!("{}", secd::Interpreter::new().eval_code(code)?);
+    Ok(())
+}
+
+fn run(file: &str, flags: Flags) -> Result<(), Box<dyn Error>> {
+    let code = optimized(load(file, flags)?, flags);
+    println!("{}", secd::Interpreter::new().eval_code(code)?);
+    Ok(())
+}
 
 fn main() {
-    let mut args = env::args();
-    if args.len() == 2 {
-        println!("{}",
-                 secd::run_lisp_file(&args.nth(1).unwrap()).expect("main"));
-    } else {
-        println!("expected 1 file");
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let flags = Flags {
+        opt: args.iter().any(|s| s == "-O"),
+        typecheck: args.iter().any(|s| s == "--typecheck"),
+    };
+    args.retain(|s| s != "-O" && s != "--typecheck");
+    let r = match args.iter().map(|s| s.as_str()).collect::<Vec<_>>().as_slice() {
+        ["compile", src, "-o", out] => compile(src, out, flags),
+        ["run", _] => run(&args[1], flags),
+        ["disasm", file] => disasm(file, flags),
+        ["asm", file] => asm(file, flags),
+        [_] => run(&args[0], flags),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(e) = r {
+        eprintln!("{}", e);
+        process::exit(1);
     }
 }
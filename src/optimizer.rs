@@ -0,0 +1,321 @@
+use data::{Code, CodeOP, CodeOPInfo, Info, Value};
+use verify::verify;
+
+use std::rc::Rc;
+use std::collections::{HashMap, HashSet};
+
+// An optional pass over compiled code, enabled with `secd -O`. It folds
+// operations on constants, drops branches on constant conditions along with
+// any code that is no longer reachable, removes let bindings of pure
+// expressions whose name is never used, and cleans up jumps.
+//
+// While rewriting, jumps and closure bodies refer to absolute instruction
+// indices; deleted instructions are compacted away after every pass, and
+// the relative offsets are recomputed at the end.
+//
+// The code must pass verify, so that every target is in range. Compiler
+// output does, and bytecode files and assembler input are verified when
+// they are loaded.
+
+struct Ins {
+    info: Info,
+    op: CodeOP,
+    // where a JMP or JMPF continues, or the end of a CLOSURE body
+    target: usize,
+    live: bool,
+}
+
+type Pass = fn(&mut [Ins]) -> bool;
+
+pub fn optimize(code: &Code) -> Code {
+    debug_assert!(verify(code).is_ok());
+    let mut ins: Vec<Ins> = code.iter()
+        .enumerate()
+        .map(|(i, op)| {
+            let target = match op.op {
+                CodeOP::CLOSURE(_, n) | CodeOP::JMP(n) | CodeOP::JMPF(n) => i + 1 + n,
+                _ => 0,
+            };
+            Ins {
+                info: op.info,
+                op: op.op.clone(),
+                target,
+                live: true,
+            }
+        })
+        .collect();
+
+    let passes: &[Pass] = &[fold, branches, unreachable, unused_lets, jumps];
+    loop {
+        let mut changed = false;
+        for pass in passes {
+            changed |= pass(&mut ins);
+            compact(&mut ins);
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    let code: Vec<CodeOPInfo> = ins.into_iter()
+        .enumerate()
+        .map(|(i, ins)| {
+            let n = ins.target.wrapping_sub(i + 1);
+            let op = match ins.op {
+                CodeOP::CLOSURE(names, _) => CodeOP::CLOSURE(names, n),
+                CodeOP::JMP(_) => CodeOP::JMP(n),
+                CodeOP::JMPF(_) => CodeOP::JMPF(n),
+                op => op,
+            };
+            CodeOPInfo { info: ins.info, op }
+        })
+        .collect();
+    Rc::new(code.into_boxed_slice())
+}
+
+fn has_target(op: &CodeOP) -> bool {
+    matches!(op, CodeOP::CLOSURE(..) | CodeOP::JMP(_) | CodeOP::JMPF(_))
+}
+
+// drops deleted instructions; references to them move to the next live one
+fn compact(ins: &mut Vec<Ins>) {
+    let mut map = Vec::with_capacity(ins.len() + 1);
+    let mut n = 0;
+    for i in ins.iter() {
+        map.push(n);
+        if i.live {
+            n += 1;
+        }
+    }
+    map.push(n);
+
+    ins.retain(|i| i.live);
+    for i in ins.iter_mut() {
+        if has_target(&i.op) {
+            i.target = map[i.target];
+        }
+    }
+}
+
+// instructions that execution can arrive at other than from the one before
+fn targets(ins: &[Ins]) -> HashSet<usize> {
+    ins.iter().filter(|i| has_target(&i.op)).map(|i| i.target).collect()
+}
+
+fn immediate(op: &CodeOP) -> Option<&Value> {
+    match op {
+        CodeOP::LDC(Value::Ref(_)) => None,
+        CodeOP::LDC(val) => Some(val),
+        _ => None,
+    }
+}
+
+fn fold1(val: &Value, op: &CodeOP) -> Option<Value> {
+    match (val, op) {
+        (Value::Char(c), CodeOP::CHAR2INT) => Some(Value::Int(*c as i32)),
+        (Value::Int(n), CodeOP::INT2CHAR) if *n >= 0 => {
+            ::std::char::from_u32(*n as u32).map(Value::Char)
+        }
+        _ => None,
+    }
+}
+
+// `a` is pushed first, so it is the left operand
+fn fold2(a: &Value, b: &Value, op: &CodeOP) -> Option<Value> {
+    match (a, b, op) {
+        (Value::Int(m), Value::Int(n), CodeOP::ADD) => m.checked_add(*n).map(Value::Int),
+        (Value::Int(m), Value::Int(n), CodeOP::SUB) => m.checked_sub(*n).map(Value::Int),
+        (Value::Char(c), Value::Char(d), CodeOP::CHAREQ) => Some(Value::bool(c == d)),
+        (Value::Char(c), Value::Char(d), CodeOP::CHARLT) => Some(Value::bool(c < d)),
+        (a, b, CodeOP::EQ) => Some(Value::bool(a == b)),
+        _ => None,
+    }
+}
+
+// LDC a; LDC b; ADD => LDC a+b, and likewise for the other pure operations
+// on constants. The result keeps the position of the operation.
+fn fold(ins: &mut [Ins]) -> bool {
+    let targets = targets(ins);
+    let mut changed = false;
+    let mut i = 0;
+    while i + 1 < ins.len() {
+        if let Some(a) = immediate(&ins[i].op).cloned() {
+            if !targets.contains(&(i + 1)) {
+                if let Some(val) = fold1(&a, &ins[i + 1].op) {
+                    ins[i].live = false;
+                    ins[i + 1].op = CodeOP::LDC(val);
+                    changed = true;
+                    i += 2;
+                    continue;
+                }
+            }
+
+            if i + 2 < ins.len() && !targets.contains(&(i + 1)) && !targets.contains(&(i + 2)) {
+                let val = match immediate(&ins[i + 1].op) {
+                    Some(b) => fold2(&a, b, &ins[i + 2].op),
+                    None => None,
+                };
+                if let Some(val) = val {
+                    ins[i].live = false;
+                    ins[i + 1].live = false;
+                    ins[i + 2].op = CodeOP::LDC(val);
+                    changed = true;
+                    i += 3;
+                    continue;
+                }
+            }
+        }
+        i += 1;
+    }
+    changed
+}
+
+// LDC true; JMPF n => nothing, and LDC false; JMPF n => JMP n
+fn branches(ins: &mut [Ins]) -> bool {
+    let targets = targets(ins);
+    let mut changed = false;
+    for i in 0..ins.len().saturating_sub(1) {
+        if !ins[i].live || targets.contains(&(i + 1)) {
+            continue;
+        }
+        if let CodeOP::JMPF(_) = ins[i + 1].op {
+            match ins[i].op {
+                CodeOP::LDC(Value::True) => {
+                    ins[i].live = false;
+                    ins[i + 1].live = false;
+                    changed = true;
+                }
+                CodeOP::LDC(Value::False) => {
+                    ins[i].live = false;
+                    ins[i + 1].op = CodeOP::JMP(0);
+                    changed = true;
+                }
+                _ => {}
+            }
+        }
+    }
+    changed
+}
+
+fn unreachable(ins: &mut [Ins]) -> bool {
+    let mut seen = vec![false; ins.len()];
+    let mut work = vec![0];
+    while let Some(i) = work.pop() {
+        if i >= ins.len() || seen[i] {
+            continue;
+        }
+        seen[i] = true;
+        match ins[i].op {
+            CodeOP::JMP(_) => work.push(ins[i].target),
+            CodeOP::JMPF(_) | CodeOP::CLOSURE(..) => {
+                work.push(i + 1);
+                work.push(ins[i].target);
+            }
+            CodeOP::RET => {}
+            _ => work.push(i + 1),
+        }
+    }
+
+    let mut changed = false;
+    for (i, seen) in seen.into_iter().enumerate() {
+        if !seen {
+            ins[i].live = false;
+            changed = true;
+        }
+    }
+    changed
+}
+
+// Removes `LDC v; LET x` and `CLOSURE ...; LET x` when x is never loaded or
+// set. Calls through RAP run in the caller's environment, so any use of the
+// name anywhere in the code counts, and nothing is removed from code that
+// evals.
+fn unused_lets(ins: &mut [Ins]) -> bool {
+    let mut used = HashSet::new();
+    for i in ins.iter() {
+        match i.op {
+            CodeOP::LD(id) | CodeOP::SET(id) => {
+                used.insert(id);
+            }
+            CodeOP::EVAL => return false,
+            _ => {}
+        }
+    }
+
+    // closure bodies by the index they end at
+    let closures: HashMap<usize, usize> = ins.iter()
+        .enumerate()
+        .filter_map(|(i, ins)| match ins.op {
+                        CodeOP::CLOSURE(..) => Some((ins.target, i)),
+                        _ => None,
+                    })
+        .collect();
+
+    let mut changed = false;
+    for i in 1..ins.len() {
+        match ins[i].op {
+            CodeOP::LET(id) if !used.contains(&id) => {}
+            _ => continue,
+        }
+        let start = match ins[i - 1].op {
+            CodeOP::LDC(_) => i - 1,
+            _ => {
+                match closures.get(&i) {
+                    Some(&start) => start,
+                    None => continue,
+                }
+            }
+        };
+        if (start..=i).any(|j| !ins[j].live) || entered(ins, start, i) {
+            continue;
+        }
+
+        for ins in &mut ins[start..=i] {
+            ins.live = false;
+        }
+        changed = true;
+    }
+    changed
+}
+
+// whether a jump from outside start..=end lands inside it after start
+fn entered(ins: &[Ins], start: usize, end: usize) -> bool {
+    ins.iter()
+        .enumerate()
+        .any(|(j, i)| {
+                 (j < start || j > end) && has_target(&i.op) && i.target > start &&
+                 i.target <= end
+             })
+}
+
+// JMP 0 => nothing, jumps to jumps go straight to the final target, and a
+// jump to RET is a RET
+fn jumps(ins: &mut [Ins]) -> bool {
+    let mut changed = false;
+    for i in 0..ins.len() {
+        match ins[i].op {
+            CodeOP::JMP(_) | CodeOP::JMPF(_) => {}
+            _ => continue,
+        }
+
+        let mut target = ins[i].target;
+        while let Some(&Ins { op: CodeOP::JMP(_), target: next, .. }) = ins.get(target) {
+            target = next;
+        }
+        if target != ins[i].target {
+            ins[i].target = target;
+            changed = true;
+        }
+
+        if let CodeOP::JMP(_) = ins[i].op {
+            if target == i + 1 {
+                ins[i].live = false;
+                changed = true;
+            } else if let Some(&Ins { op: CodeOP::RET, .. }) = ins.get(target) {
+                ins[i].op = CodeOP::RET;
+                changed = true;
+            }
+        }
+    }
+    changed
+}
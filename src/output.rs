@@ -0,0 +1,85 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, Write};
+
+// Where puts and write send their text. Each printed line reaches the sink
+// with a single write_all call, so callbacks see whole lines.
+pub struct Output(Box<dyn Write>);
+
+// an in-memory sink whose contents stay readable after the machine is done
+#[derive(Clone, Default)]
+pub struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+struct Callback<F>(F);
+
+impl Output {
+    pub fn stdout() -> Output {
+        Output(Box::new(io::stdout()))
+    }
+
+    pub fn writer<W: Write + 'static>(w: W) -> Output {
+        Output(Box::new(w))
+    }
+
+    pub fn buffer() -> (Output, Buffer) {
+        let buf = Buffer::default();
+        (Output(Box::new(buf.clone())), buf)
+    }
+
+    pub fn callback<F: FnMut(&str) + 'static>(f: F) -> Output {
+        Output(Box::new(Callback(f)))
+    }
+
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let mut s = String::with_capacity(line.len() + 1);
+        s.push_str(line);
+        s.push('\n');
+        self.0.write_all(s.as_bytes())?;
+        self.0.flush()
+    }
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Output::stdout()
+    }
+}
+
+impl fmt::Debug for Output {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Output")
+    }
+}
+
+impl Buffer {
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+
+    pub fn clear(&self) {
+        self.0.borrow_mut().clear();
+    }
+}
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<F: FnMut(&str)> Write for Callback<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (self.0)(&String::from_utf8_lossy(buf));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
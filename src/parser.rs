@@ -1,5 +1,5 @@
 
-use data::{Info, AST, SExpr};
+use data::{Info, AST, SExpr, char_from_name};
 
 use std::error::Error;
 
@@ -7,6 +7,7 @@ pub struct Parser {
     src: String,
     pos: usize,
     info: Info,
+    unclosed: usize,
 }
 
 pub struct Token {
@@ -15,8 +16,8 @@ pub struct Token {
     pub info: Info,
 }
 
-type LexerResult = Result<Option<Token>, Box<Error>>;
-type ParserResult = Result<AST, Box<Error>>;
+type LexerResult = Result<Option<Token>, Box<dyn Error>>;
+type ParserResult = Result<AST, Box<dyn Error>>;
 
 fn is_id(c: char) -> bool {
     "1234567890!#$%&-^=~|@`;:+*,./_<>?_qwertyuiopasdfghjklzxcvbnmQWERTYUIOPASDFGHJKLZXCVBNM"
@@ -25,11 +26,13 @@ fn is_id(c: char) -> bool {
 }
 
 impl Parser {
+    #[allow(clippy::ptr_arg)]
     pub fn new(s: &String) -> Parser {
         Parser {
             src: s.clone(),
             pos: 0,
             info: [1; 2],
+            unclosed: 0,
         }
     }
 
@@ -51,8 +54,8 @@ impl Parser {
         let prev_info = self.info;
         let mut t = Ok(None);
 
-        while self.src.len() > self.pos {
-            match self.src.as_bytes()[self.pos] as char {
+        while let Some(c) = self.src[self.pos..].chars().next() {
+            match c {
                 '(' => {
                     self.inc_width();
                     self.inc_pos();
@@ -64,6 +67,59 @@ impl Parser {
                     break;
                 }
 
+                '#' if self.src.as_bytes().get(self.pos + 1) == Some(&b'(') => {
+                    self.inc_width();
+                    self.inc_width();
+                    self.inc_pos();
+                    self.inc_pos();
+                    t = Ok(Some(Token {
+                                    token: String::from("#("),
+                                    kind: "#(",
+                                    info: self.info,
+                                }));
+                    break;
+                }
+
+                '#' if self.src.as_bytes().get(self.pos + 1) == Some(&b'\\') => {
+                    self.inc_width();
+                    self.inc_width();
+                    self.inc_pos();
+                    self.inc_pos();
+
+                    let c = match self.src[self.pos..].chars().next() {
+                        Some(c) => c,
+                        None => {
+                            t = Err(From::from(format!("lex expected char in {:?}", self.info)));
+                            break;
+                        }
+                    };
+                    self.inc_width();
+                    self.pos += c.len_utf8();
+
+                    let mut s = String::new();
+                    s.push(c);
+
+                    if c.is_alphabetic() {
+                        while let Some(cc) = self.src[self.pos..].chars().next() {
+                            if cc.is_alphabetic() {
+                                self.inc_width();
+                                self.pos += cc.len_utf8();
+
+                                s.push(cc);
+                            } else {
+                                break;
+                            }
+                        }
+                    }
+
+                    t = Ok(Some(Token {
+                                    token: s,
+                                    kind: "char",
+                                    info: self.info,
+                                }));
+                    break;
+                }
+
                 ')' => {
                     self.inc_width();
                     self.inc_pos();
@@ -87,28 +143,27 @@ impl Parser {
 
                 c if c.is_numeric() => {
                     self.inc_width();
-                    self.inc_pos();
+                    self.pos += c.len_utf8();
 
                     let mut s = String::new();
                     s.push(c);
 
-                    while self.src.len() > self.pos {
-                        let cc = self.src.as_bytes()[self.pos] as char;
+                    while let Some(cc) = self.src[self.pos..].chars().next() {
                         if cc.is_numeric() {
                             self.inc_width();
-                            self.inc_pos();
+                            self.pos += cc.len_utf8();
 
                             s.push(cc);
                         } else {
-                            t = Ok(Some(Token {
-                                            token: s,
-                                            kind: "int",
-                                            info: self.info,
-                                        }));
                             break;
                         }
                     }
 
+                    t = Ok(Some(Token {
+                                    token: s,
+                                    kind: "int",
+                                    info: self.info,
+                                }));
                     break;
                 }
 
@@ -119,23 +174,22 @@ impl Parser {
                     let mut s = String::new();
                     s.push(c);
 
-                    while self.src.len() > self.pos {
-                        let cc = self.src.as_bytes()[self.pos] as char;
+                    while let Some(cc) = self.src[self.pos..].chars().next() {
                         if is_id(cc) {
                             self.inc_width();
-                            self.inc_pos();
+                            self.pos += cc.len_utf8();
 
                             s.push(cc);
                         } else {
-                            t = Ok(Some(Token {
-                                            token: s,
-                                            kind: "id",
-                                            info: self.info,
-                                        }));
                             break;
                         }
                     }
 
+                    t = Ok(Some(Token {
+                                    token: s,
+                                    kind: "id",
+                                    info: self.info,
+                                }));
                     break;
                 }
 
@@ -154,6 +208,7 @@ impl Parser {
         t
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> LexerResult {
         self.lex(false)
     }
@@ -162,9 +217,22 @@ impl Parser {
         self.lex(true)
     }
 
+    // the program is its last top-level expression
     pub fn parse(&mut self) -> ParserResult {
+        let mut last = None;
+        while let Some(ast) = self.read()? {
+            last = Some(ast);
+        }
+
+        last.ok_or_else(|| From::from("empty program".to_string()))
+    }
+
+    // reads a single datum, leaving the rest of the source for the next call
+    pub fn read(&mut self) -> Result<Option<AST>, Box<dyn Error>> {
+        self.unclosed = 0;
         let mut ps = 0;
         let mut list: Vec<Vec<AST>> = vec![Vec::new()];
+        let mut opens: Vec<&'static str> = Vec::new();
 
         loop {
             match self.next()? {
@@ -182,26 +250,60 @@ impl Parser {
                         }
 
                         "int" => {
+                            let n = match t.token.parse() {
+                                Ok(n) => n,
+                                Err(_) => {
+                                    return Err(From::from(format!("bad int '{}' in {:?}",
+                                                                  t.token,
+                                                                  t.info)))
+                                }
+                            };
                             list.last_mut()
                                 .unwrap()
                                 .push(AST {
                                           info: t.info,
-                                          sexpr: SExpr::Int(t.token.parse().unwrap()),
+                                          sexpr: SExpr::Int(n),
                                       })
                         }
 
-                        "(" => {
+                        "char" => {
+                            let c = match char_from_name(&t.token) {
+                                Some(c) => c,
+                                None => {
+                                    return Err(From::from(format!("unknown char name '{}' in {:?}",
+                                                                  t.token,
+                                                                  t.info)))
+                                }
+                            };
+                            list.last_mut()
+                                .unwrap()
+                                .push(AST {
+                                          info: t.info,
+                                          sexpr: SExpr::Char(c),
+                                      })
+                        }
+
+                        "(" | "#(" => {
                             list.push(Vec::new());
+                            opens.push(t.kind);
                             ps += 1;
                         }
 
                         ")" => {
+                            if ps == 0 {
+                                return Err(From::from("many ')'".to_string()));
+                            }
+
                             let node = list.pop().unwrap();
+                            let sexpr = match opens.pop().unwrap() {
+                                "#(" => SExpr::Vector(node),
+                                _ => SExpr::List(node),
+                            };
                             list.last_mut()
                                 .unwrap()
                                 .push(AST {
                                           info: t.info,
-                                          sexpr: SExpr::List(node),
+                                          sexpr,
                                       });
                             ps -= 1;
                         }
@@ -209,17 +311,27 @@ impl Parser {
                         _ => unimplemented!(),
                     }
 
-                    if ps < 0 {
-                        return Err(From::from("many ')'".to_string()));
+                    if ps == 0 {
+                        return Ok(list.pop().unwrap().pop());
                     }
                 }
             }
         }
 
+        self.unclosed = ps;
         if ps > 0 {
             Err(From::from("many '('".to_string()))
         } else {
-            Ok(list.pop().unwrap().pop().unwrap())
+            Ok(None)
         }
     }
+
+    // number of lists left open when the last read hit the end of the source
+    pub fn unclosed(&self) -> usize {
+        self.unclosed
+    }
+
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
 }
@@ -0,0 +1,281 @@
+use data::{Lisp, Value};
+use symbol::Symbol;
+
+use std::rc::Rc;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+pub const DEFAULT_MAX_DEPTH: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Display,
+    Write,
+}
+
+// Prints lists as (1 2 3) / (1 . 2). Cdr chains are walked iteratively, while
+// nesting deeper than max_depth is cut off with "...". Values that are part of
+// a cycle are printed with datum labels, e.g. #0=(1 . #0#).
+pub struct Printer {
+    mode: Mode,
+    max_depth: usize,
+    labels: HashMap<*const Lisp, Option<usize>>,
+    next_label: usize,
+}
+
+pub fn display(val: &Value) -> String {
+    let mut s = String::new();
+    Printer::new(Mode::Display).print(&mut s, val).unwrap();
+    s
+}
+
+pub fn write(val: &Value) -> String {
+    let mut s = String::new();
+    Printer::new(Mode::Write).print(&mut s, val).unwrap();
+    s
+}
+
+pub fn write_char(f: &mut dyn fmt::Write, c: char) -> fmt::Result {
+    match c {
+        ' ' => write!(f, "#\\space"),
+        '\n' => write!(f, "#\\newline"),
+        '\t' => write!(f, "#\\tab"),
+        c => write!(f, "#\\{}", c),
+    }
+}
+
+// parameter names separated by spaces
+pub fn join(names: &[Symbol]) -> String {
+    names.iter().map(|name| name.as_str()).collect::<Vec<_>>().join(" ")
+}
+
+// the heap objects a value refers to directly
+fn children(lisp: &Lisp) -> Vec<Rc<Lisp>> {
+    let vals = match lisp {
+        Lisp::Cons(car, cdr) => vec![car.borrow().clone(), cdr.borrow().clone()],
+        Lisp::Box(val) => vec![val.borrow().clone()],
+        Lisp::List(ls) => ls.clone(),
+        Lisp::Vector(vals) => vals.borrow().clone(),
+        Lisp::HashTable(table) => {
+            table.borrow()
+                .iter()
+                .flat_map(|(k, v)| vec![k.clone(), v.clone()])
+                .collect()
+        }
+        _ => Vec::new(),
+    };
+    vals.into_iter()
+        .filter_map(|val| match val {
+                        Value::Ref(l) => Some(l),
+                        _ => None,
+                    })
+        .collect()
+}
+
+// iterative dfs, collecting every value reached again while still on the path
+fn find_cycles(root: &Lisp) -> HashSet<*const Lisp> {
+    enum Visit {
+        Enter(Rc<Lisp>),
+        Exit(*const Lisp),
+    }
+
+    let mut cycles = HashSet::new();
+    let mut on_path = HashSet::new();
+    let mut done = HashSet::new();
+
+    on_path.insert(root as *const Lisp);
+    let mut stack = vec![Visit::Exit(root as *const Lisp)];
+    stack.extend(children(root).into_iter().rev().map(Visit::Enter));
+
+    while let Some(visit) = stack.pop() {
+        match visit {
+            Visit::Enter(lisp) => {
+                let p = &*lisp as *const Lisp;
+                if on_path.contains(&p) {
+                    cycles.insert(p);
+                } else if !done.contains(&p) {
+                    let cs = children(&lisp);
+                    if !cs.is_empty() {
+                        on_path.insert(p);
+                        stack.push(Visit::Exit(p));
+                        stack.extend(cs.into_iter().rev().map(Visit::Enter));
+                    }
+                }
+            }
+
+            Visit::Exit(p) => {
+                on_path.remove(&p);
+                done.insert(p);
+            }
+        }
+    }
+
+    cycles
+}
+
+impl Printer {
+    pub fn new(mode: Mode) -> Printer {
+        Printer {
+            mode,
+            max_depth: DEFAULT_MAX_DEPTH,
+            labels: HashMap::new(),
+            next_label: 0,
+        }
+    }
+
+    pub fn max_depth(mut self, n: usize) -> Printer {
+        self.max_depth = n;
+        self
+    }
+
+    pub fn print(&mut self, f: &mut dyn fmt::Write, val: &Value) -> fmt::Result {
+        match val {
+            Value::Ref(l) => self.print_lisp(f, l),
+            _ => self.print_(f, val, 0),
+        }
+    }
+
+    pub fn print_lisp(&mut self, f: &mut dyn fmt::Write, lisp: &Lisp) -> fmt::Result {
+        self.labels = find_cycles(lisp).into_iter().map(|p| (p, None)).collect();
+        self.next_label = 0;
+        self.print_heap(f, lisp, 0)
+    }
+
+    // emits "#n=" the first time a labeled value is printed and "#n#" after;
+    // returns true when only the back reference was printed
+    fn label(&mut self, f: &mut dyn fmt::Write, lisp: &Lisp) -> Result<bool, fmt::Error> {
+        match self.labels.get_mut(&(lisp as *const Lisp)) {
+            Some(&mut Some(n)) => {
+                write!(f, "#{}#", n)?;
+                Ok(true)
+            }
+
+            Some(label) => {
+                *label = Some(self.next_label);
+                write!(f, "#{}=", self.next_label)?;
+                self.next_label += 1;
+                Ok(false)
+            }
+
+            None => Ok(false),
+        }
+    }
+
+    fn print_(&mut self, f: &mut dyn fmt::Write, val: &Value, depth: usize) -> fmt::Result {
+        if depth > self.max_depth {
+            return write!(f, "...");
+        }
+
+        match val {
+            Value::Nil => write!(f, "nil"),
+            Value::True => write!(f, "true"),
+            Value::False => write!(f, "false"),
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Char(c) => {
+                match self.mode {
+                    Mode::Display => write!(f, "{}", c),
+                    Mode::Write => write_char(f, *c),
+                }
+            }
+            Value::Symbol(id) => write!(f, "{}", id),
+            Value::Ref(l) => self.print_heap(f, l, depth),
+        }
+    }
+
+    fn print_heap(&mut self, f: &mut dyn fmt::Write, lisp: &Lisp, depth: usize) -> fmt::Result {
+        match lisp {
+            Lisp::Closure(args, ..) => {
+                // without the suffix the compiler gives each binder
+                let names: Vec<&str> =
+                    args.iter().map(|a| a.as_str().split('\'').next().unwrap()).collect();
+                write!(f, "#<closure ({})>", names.join(" "))
+            }
+            Lisp::Native(native) => write!(f, "#<native {}>", native.name),
+            Lisp::Cons(car, cdr) => {
+                if self.label(f, lisp)? {
+                    return Ok(());
+                }
+
+                write!(f, "(")?;
+                self.print_(f, &car.borrow(), depth + 1)?;
+                let mut rest = cdr.borrow().clone();
+                loop {
+                    let next = match rest {
+                        Value::Nil => break,
+                        Value::Ref(ref l) if !self.labels.contains_key(&Rc::as_ptr(l)) => {
+                            match **l {
+                                Lisp::Cons(ref car, ref cdr) => {
+                                    write!(f, " ")?;
+                                    self.print_(f, &car.borrow(), depth + 1)?;
+                                    cdr.borrow().clone()
+                                }
+                                _ => {
+                                    write!(f, " . ")?;
+                                    self.print_(f, &rest, depth + 1)?;
+                                    break;
+                                }
+                            }
+                        }
+                        _ => {
+                            write!(f, " . ")?;
+                            self.print_(f, &rest, depth + 1)?;
+                            break;
+                        }
+                    };
+                    rest = next;
+                }
+                write!(f, ")")
+            }
+            Lisp::Box(val) => {
+                if self.label(f, lisp)? {
+                    return Ok(());
+                }
+
+                write!(f, "#&")?;
+                self.print_(f, &val.borrow(), depth + 1)
+            }
+            Lisp::List(ls) => self.print_seq(f, "(", ls, depth),
+            Lisp::Vector(vals) => {
+                if self.label(f, lisp)? {
+                    return Ok(());
+                }
+
+                self.print_seq(f, "#(", &vals.borrow(), depth)
+            }
+            Lisp::HashTable(table) => {
+                if self.label(f, lisp)? {
+                    return Ok(());
+                }
+
+                write!(f, "#hash(")?;
+                for (i, (key, val)) in table.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "(")?;
+                    self.print_(f, key, depth + 1)?;
+                    write!(f, " . ")?;
+                    self.print_(f, val, depth + 1)?;
+                    write!(f, ")")?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+
+    fn print_seq(&mut self,
+                 f: &mut dyn fmt::Write,
+                 open: &str,
+                 vals: &[Value],
+                 depth: usize)
+                 -> fmt::Result {
+        write!(f, "{}", open)?;
+        for (i, val) in vals.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            self.print_(f, val, depth + 1)?;
+        }
+        write!(f, ")")
+    }
+}
@@ -0,0 +1,908 @@
+use data::*;
+use limits::Limits;
+use symbol::Symbol;
+use verify;
+
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::convert::TryFrom;
+
+// Binary snapshots of a machine.
+//
+// Everything reachable from the registers is written once into a table of
+// nodes (heap objects, env cells, code blocks and parameter lists) and
+// referred to by index, so sharing and cycles survive the round trip. A node
+// may only refer to earlier nodes, except through mutable slots (cons cells,
+// boxes, vectors, hash tables and env cells), which are patched once the whole
+// table has been read. Immutable references can not form cycles, so this
+// ordering always exists.
+//
+// Immediate values are written inline wherever they are referred to.
+// Host functions can not be written; they are stored by name and arity and
+// resolved again on restore. Output is not part of the snapshot.
+//
+// A restored machine must not be able to crash, so every code block has to
+// pass verify, closures have to start at a closure body, and the machine and
+// every frame on the dump have to resume with as many values on the stack as
+// the code there needs.
+
+const MAGIC: &[u8; 8] = b"SECDSNAP";
+pub const VERSION: u32 = 4;
+
+type Cell = Rc<RefCell<Value>>;
+type Names = Rc<Box<[Symbol]>>;
+// the stack depths verify found for each code block
+type Depths = HashMap<*const [CodeOPInfo], Vec<Option<usize>>>;
+
+#[derive(Clone)]
+pub(crate) enum Node {
+    Lisp(Rc<Lisp>),
+    Cell(Cell),
+    Code(Code),
+    Names(Names),
+}
+
+impl Node {
+    fn key(&self) -> *const () {
+        match self {
+            Node::Lisp(l) => Rc::as_ptr(l) as *const (),
+            Node::Cell(c) => Rc::as_ptr(c) as *const (),
+            Node::Code(c) => Rc::as_ptr(c) as *const (),
+            Node::Names(n) => Rc::as_ptr(n) as *const (),
+        }
+    }
+}
+
+// envs are walked in name order so the same state always gives the same
+// bytes; hash tables are not. Symbol ids depend on interning order, so the
+// names themselves are compared.
+fn sorted(env: &Env) -> Vec<(&Symbol, &Cell)> {
+    let mut entries: Vec<_> = env.iter().collect();
+    entries.sort_by_key(|entry| entry.0.as_str());
+    entries
+}
+
+#[derive(Default)]
+pub(crate) struct Encoder {
+    ids: HashMap<*const (), u32>,
+    nodes: Vec<Node>,
+    work: Vec<Node>,
+    pub out: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Encoder::default()
+    }
+
+    // Numbers every node reachable from `node`. Immutable references are
+    // numbered first; mutable slots are queued for later.
+    pub fn add(&mut self, node: Node) {
+        self.visit(node);
+        while let Some(node) = self.work.pop() {
+            self.visit(node);
+        }
+    }
+
+    pub fn add_value(&mut self, val: &Value) {
+        if let Value::Ref(l) = val {
+            self.add(Node::Lisp(l.clone()));
+        }
+    }
+
+    fn visit_value(&mut self, val: &Value) {
+        if let Value::Ref(l) = val {
+            self.visit(Node::Lisp(l.clone()));
+        }
+    }
+
+    fn defer(&mut self, val: &Value) {
+        if let Value::Ref(l) = val {
+            self.work.push(Node::Lisp(l.clone()));
+        }
+    }
+
+    fn visit(&mut self, node: Node) {
+        if self.ids.contains_key(&node.key()) {
+            return;
+        }
+
+        match node {
+            Node::Lisp(ref l) => {
+                match **l {
+                    Lisp::List(ref vals) => {
+                        for val in vals {
+                            self.visit_value(val);
+                        }
+                    }
+                    Lisp::Closure(ref names, ref code, _, ref env) => {
+                        self.visit(Node::Names(names.clone()));
+                        self.visit(Node::Code(code.clone()));
+                        for (_, cell) in sorted(env) {
+                            self.visit(Node::Cell(cell.clone()));
+                        }
+                    }
+                    Lisp::Cons(ref car, ref cdr) => {
+                        self.defer(&car.borrow());
+                        self.defer(&cdr.borrow());
+                    }
+                    Lisp::Box(ref val) => {
+                        self.defer(&val.borrow());
+                    }
+                    Lisp::Vector(ref vals) => {
+                        for val in vals.borrow().iter() {
+                            self.defer(val);
+                        }
+                    }
+                    Lisp::HashTable(ref table) => {
+                        for (k, v) in table.borrow().iter() {
+                            self.defer(k);
+                            self.defer(v);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Node::Cell(ref cell) => {
+                self.defer(&cell.borrow());
+            }
+            Node::Code(ref code) => {
+                for op in code.iter() {
+                    match op.op {
+                        CodeOP::LDC(ref val) => self.visit_value(val),
+                        CodeOP::CLOSURE(ref names, _) => self.visit(Node::Names(names.clone())),
+                        _ => {}
+                    }
+                }
+            }
+            Node::Names(_) => {}
+        }
+
+        self.ids.insert(node.key(), self.nodes.len() as u32);
+        self.nodes.push(node);
+    }
+
+    pub fn id(&self, node: &Node) -> u32 {
+        self.ids[&node.key()]
+    }
+
+    pub fn header(&mut self, magic: &[u8; 8], version: u32) {
+        self.out.extend_from_slice(magic);
+        self.u32(version);
+    }
+
+    pub fn u8(&mut self, n: u8) {
+        self.out.push(n);
+    }
+
+    pub fn u32(&mut self, n: u32) {
+        self.out.extend_from_slice(&n.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, n: u64) {
+        self.out.extend_from_slice(&n.to_le_bytes());
+    }
+
+    pub fn str(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.out.extend_from_slice(s.as_bytes());
+    }
+
+    // immediates inline, heap objects by reference
+    fn value(&mut self, val: &Value) {
+        match val {
+            Value::Nil => self.u8(0),
+            Value::False => self.u8(1),
+            Value::True => self.u8(2),
+            Value::Int(n) => {
+                self.u8(3);
+                self.u32(*n as u32);
+            }
+            Value::Char(c) => {
+                self.u8(4);
+                self.u32(*c as u32);
+            }
+            Value::Symbol(id) => {
+                self.u8(5);
+                self.str(id.as_str());
+            }
+            Value::Ref(l) => {
+                self.u8(6);
+                let id = self.id(&Node::Lisp(l.clone()));
+                self.u32(id);
+            }
+        }
+    }
+
+    fn cell_ref(&mut self, c: &Cell) {
+        let id = self.id(&Node::Cell(c.clone()));
+        self.u32(id);
+    }
+
+    fn code_ref(&mut self, c: &Code) {
+        let id = self.id(&Node::Code(c.clone()));
+        self.u32(id);
+    }
+
+    fn names_ref(&mut self, n: &Names) {
+        let id = self.id(&Node::Names(n.clone()));
+        self.u32(id);
+    }
+
+    pub fn env(&mut self, env: &Env) {
+        self.u32(env.len() as u32);
+        for (name, cell) in sorted(env) {
+            self.str(name.as_str());
+            self.cell_ref(cell);
+        }
+    }
+
+    pub fn code_pos(&mut self, code: &(Code, CodePos)) {
+        self.code_ref(&code.0);
+        self.u64(code.1 as u64);
+    }
+
+    pub fn stack(&mut self, stack: &[Value]) {
+        self.u32(stack.len() as u32);
+        for val in stack {
+            self.value(val);
+        }
+    }
+
+    // writes the node table; must come after every add
+    pub fn nodes(&mut self) {
+        let nodes = self.nodes.clone();
+        self.u32(nodes.len() as u32);
+        for node in &nodes {
+            match node {
+                Node::Lisp(l) => self.lisp(l),
+                Node::Cell(c) => {
+                    self.u8(32);
+                    self.value(&c.borrow());
+                }
+                Node::Code(c) => {
+                    self.u8(33);
+                    self.u32(c.len() as u32);
+                    for op in c.iter() {
+                        self.op(op);
+                    }
+                }
+                Node::Names(n) => {
+                    self.u8(34);
+                    self.u32(n.len() as u32);
+                    for name in n.iter() {
+                        self.str(name.as_str());
+                    }
+                }
+            }
+        }
+    }
+
+    fn lisp(&mut self, l: &Lisp) {
+        match l {
+            Lisp::List(vals) => {
+                self.u8(6);
+                self.stack(vals);
+            }
+            Lisp::Closure(names, code, pos, env) => {
+                self.u8(7);
+                self.names_ref(names);
+                self.code_pos(&(code.clone(), *pos));
+                self.env(env);
+            }
+            Lisp::Native(native) => {
+                self.u8(8);
+                self.str(&native.name);
+                self.u64(native.arity as u64);
+            }
+            Lisp::Cons(car, cdr) => {
+                self.u8(9);
+                self.value(&car.borrow());
+                self.value(&cdr.borrow());
+            }
+            Lisp::Box(val) => {
+                self.u8(10);
+                self.value(&val.borrow());
+            }
+            Lisp::Vector(vals) => {
+                self.u8(11);
+                self.stack(&vals.borrow());
+            }
+            Lisp::HashTable(table) => {
+                self.u8(12);
+                let table = table.borrow();
+                self.u32(table.len() as u32);
+                for (k, v) in table.iter() {
+                    self.value(k);
+                    self.value(v);
+                }
+            }
+        }
+    }
+
+    fn op(&mut self, op: &CodeOPInfo) {
+        self.u64(op.info[0] as u64);
+        self.u64(op.info[1] as u64);
+        match op.op {
+            CodeOP::LET(ref id) => {
+                self.u8(0);
+                self.str(id.as_str());
+            }
+            CodeOP::LD(ref id) => {
+                self.u8(1);
+                self.str(id.as_str());
+            }
+            CodeOP::SET(ref id) => {
+                self.u8(2);
+                self.str(id.as_str());
+            }
+            CodeOP::DEF(ref id) => {
+                self.u8(3);
+                self.str(id.as_str());
+            }
+            CodeOP::LDC(ref l) => {
+                self.u8(4);
+                self.value(l);
+            }
+            CodeOP::CLOSURE(ref names, n) => {
+                self.u8(5);
+                self.names_ref(names);
+                self.u64(n as u64);
+            }
+            CodeOP::JMP(n) => {
+                self.u8(6);
+                self.u64(n as u64);
+            }
+            CodeOP::JMPF(n) => {
+                self.u8(7);
+                self.u64(n as u64);
+            }
+            CodeOP::ARGS(n) => {
+                self.u8(8);
+                self.u64(n as u64);
+            }
+            CodeOP::READ(n) => {
+                self.u8(9);
+                self.u64(n as u64);
+            }
+            CodeOP::VECTOR(n) => {
+                self.u8(10);
+                self.u64(n as u64);
+            }
+            ref op => {
+                // encoded as 16 + their index in SIMPLE_OPS
+                let tag = SIMPLE_OPS.iter().position(|o| o == op).unwrap();
+                self.u8(16 + tag as u8);
+            }
+        }
+    }
+}
+
+// a value whose node may not have been read yet
+enum Slot {
+    Value(Value),
+    Node(u32),
+}
+
+// a mutable slot to fill once every node exists
+enum Patch {
+    Cons(Rc<Lisp>, Slot, Slot),
+    Box(Rc<Lisp>, Slot),
+    Vector(Rc<Lisp>, Vec<Slot>),
+    HashTable(Rc<Lisp>, Vec<(Slot, Slot)>),
+    Cell(Cell, Slot),
+}
+
+pub(crate) struct Decoder<'a, 'b> {
+    buf: &'a [u8],
+    pos: usize,
+    nodes: Vec<Node>,
+    patches: Vec<Patch>,
+    natives: &'b dyn Fn(&str) -> Option<Native>,
+    kind: &'static str,
+}
+
+impl<'a, 'b> Decoder<'a, 'b> {
+    // `kind` names the format in error messages
+    pub fn new(buf: &'a [u8],
+               kind: &'static str,
+               natives: &'b dyn Fn(&str) -> Option<Native>)
+               -> Self {
+        Decoder {
+            buf,
+            pos: 0,
+            nodes: Vec::new(),
+            patches: Vec::new(),
+            natives,
+            kind,
+        }
+    }
+
+    pub fn error<T>(&self, msg: &str) -> Result<T, Box<dyn Error>> {
+        Err(From::from(format!("{} error: {}", self.kind, msg)))
+    }
+
+    pub fn header(&mut self, magic: &[u8; 8], version: u32) -> Result<(), Box<dyn Error>> {
+        if self.buf.len() < magic.len() || &self.buf[..magic.len()] != magic {
+            return self.error("bad magic number");
+        }
+        self.pos = magic.len();
+        let v = self.u32()?;
+        if v != version {
+            return self.error(&format!("unsupported version {} (expected {})", v, version));
+        }
+        Ok(())
+    }
+
+    pub fn finish(&self) -> Result<(), Box<dyn Error>> {
+        if self.pos != self.buf.len() {
+            return self.error("trailing data");
+        }
+        Ok(())
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        if self.buf.len() - self.pos < n {
+            return self.error("unexpected end of data");
+        }
+        let bytes = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, Box<dyn Error>> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> Result<u32, Box<dyn Error>> {
+        let mut b = [0; 4];
+        b.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_le_bytes(b))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, Box<dyn Error>> {
+        let mut b = [0; 8];
+        b.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(b))
+    }
+
+    pub fn usize(&mut self) -> Result<usize, Box<dyn Error>> {
+        let n = self.u64()?;
+        match usize::try_from(n) {
+            Ok(n) => Ok(n),
+            Err(_) => self.error(&format!("{} out of range", n)),
+        }
+    }
+
+    // a length prefix, checked against the remaining input so corrupt data
+    // can not ask for huge allocations
+    fn len(&mut self) -> Result<usize, Box<dyn Error>> {
+        let n = self.u32()? as usize;
+        if n > self.buf.len() - self.pos {
+            return self.error("unexpected end of data");
+        }
+        Ok(n)
+    }
+
+    pub fn str(&mut self) -> Result<String, Box<dyn Error>> {
+        let n = self.len()?;
+        match ::std::str::from_utf8(self.bytes(n)?) {
+            Ok(s) => Ok(s.to_string()),
+            Err(_) => self.error("invalid utf-8 in string"),
+        }
+    }
+
+    // symbols are stored by name and interned again when read
+    fn sym(&mut self) -> Result<Symbol, Box<dyn Error>> {
+        Ok(Symbol::intern(&self.str()?))
+    }
+
+    fn node(&mut self) -> Result<&Node, Box<dyn Error>> {
+        let id = self.u32()? as usize;
+        match self.nodes.get(id) {
+            Some(node) => Ok(node),
+            None => self.error(&format!("bad reference {}", id)),
+        }
+    }
+
+    fn slot(&mut self) -> Result<Slot, Box<dyn Error>> {
+        let val = match self.u8()? {
+            0 => Value::Nil,
+            1 => Value::False,
+            2 => Value::True,
+            3 => Value::Int(self.u32()? as i32),
+            4 => {
+                let n = self.u32()?;
+                match ::std::char::from_u32(n) {
+                    Some(c) => Value::Char(c),
+                    None => return self.error(&format!("invalid char {}", n)),
+                }
+            }
+            5 => Value::Symbol(self.sym()?),
+            6 => return Ok(Slot::Node(self.u32()?)),
+            tag => return self.error(&format!("bad value tag {}", tag)),
+        };
+        Ok(Slot::Value(val))
+    }
+
+    fn resolve(&self, slot: Slot) -> Result<Value, Box<dyn Error>> {
+        match slot {
+            Slot::Value(val) => Ok(val),
+            Slot::Node(id) => {
+                match self.nodes.get(id as usize) {
+                    Some(Node::Lisp(l)) => Ok(Value::Ref(l.clone())),
+                    Some(_) => self.error("expected a value reference"),
+                    None => self.error(&format!("bad reference {}", id)),
+                }
+            }
+        }
+    }
+
+    pub fn value(&mut self) -> Result<Value, Box<dyn Error>> {
+        let slot = self.slot()?;
+        self.resolve(slot)
+    }
+
+    fn cell_ref(&mut self) -> Result<Cell, Box<dyn Error>> {
+        match self.node()? {
+            Node::Cell(c) => Ok(c.clone()),
+            _ => self.error("expected an env cell reference"),
+        }
+    }
+
+    pub fn code_ref(&mut self) -> Result<Code, Box<dyn Error>> {
+        match self.node()? {
+            Node::Code(c) => Ok(c.clone()),
+            _ => self.error("expected a code reference"),
+        }
+    }
+
+    fn names_ref(&mut self) -> Result<Names, Box<dyn Error>> {
+        match self.node()? {
+            Node::Names(n) => Ok(n.clone()),
+            _ => self.error("expected a parameter list reference"),
+        }
+    }
+
+    pub fn env(&mut self) -> Result<Env, Box<dyn Error>> {
+        let n = self.len()?;
+        let mut env = Env::default();
+        for _ in 0..n {
+            let name = self.sym()?;
+            let cell = self.cell_ref()?;
+            env.insert(name, cell);
+        }
+        Ok(env)
+    }
+
+    pub fn code_pos(&mut self) -> Result<(Code, CodePos), Box<dyn Error>> {
+        let code = self.code_ref()?;
+        let pos = self.usize()?;
+        if pos > code.len() {
+            return self.error(&format!("code position {} out of range", pos));
+        }
+        Ok((code, pos))
+    }
+
+    pub fn stack(&mut self) -> Result<Stack, Box<dyn Error>> {
+        let n = self.len()?;
+        (0..n).map(|_| self.value()).collect()
+    }
+
+    fn slots(&mut self, n: usize) -> Result<Vec<Slot>, Box<dyn Error>> {
+        (0..n).map(|_| self.slot()).collect()
+    }
+
+    // Reads the node table. References between nodes may only point
+    // backwards, except from mutable slots, which are checked and filled in
+    // afterwards.
+    pub fn nodes(&mut self) -> Result<(), Box<dyn Error>> {
+        let n = self.len()?;
+        for _ in 0..n {
+            let node = match self.u8()? {
+                32 => {
+                    let cell = Rc::new(RefCell::new(Value::Nil));
+                    let slot = self.slot()?;
+                    self.patches.push(Patch::Cell(cell.clone(), slot));
+                    Node::Cell(cell)
+                }
+                33 => {
+                    let len = self.len()?;
+                    let mut code = Vec::with_capacity(len);
+                    for _ in 0..len {
+                        code.push(self.op()?);
+                    }
+                    Node::Code(Rc::new(code.into_boxed_slice()))
+                }
+                34 => {
+                    let len = self.len()?;
+                    let names = (0..len).map(|_| self.sym()).collect::<Result<Vec<_>, _>>()?;
+                    Node::Names(Rc::new(names.into_boxed_slice()))
+                }
+                tag => Node::Lisp(self.lisp(tag)?),
+            };
+            self.nodes.push(node);
+        }
+
+        let mut tables = Vec::new();
+        for patch in ::std::mem::take(&mut self.patches) {
+            match patch {
+                Patch::Cons(l, car, cdr) => {
+                    if let Lisp::Cons(ref a, ref d) = *l {
+                        *a.borrow_mut() = self.resolve(car)?;
+                        *d.borrow_mut() = self.resolve(cdr)?;
+                    }
+                }
+                Patch::Box(l, slot) => {
+                    if let Lisp::Box(ref val) = *l {
+                        *val.borrow_mut() = self.resolve(slot)?;
+                    }
+                }
+                Patch::Vector(l, slots) => {
+                    if let Lisp::Vector(ref vals) = *l {
+                        for (val, slot) in vals.borrow_mut().iter_mut().zip(slots) {
+                            *val = self.resolve(slot)?;
+                        }
+                    }
+                }
+                Patch::Cell(cell, slot) => {
+                    *cell.borrow_mut() = self.resolve(slot)?;
+                }
+                patch => tables.push(patch),
+            }
+        }
+
+        // keys are hashed on insert, so tables are filled once their keys
+        // are complete
+        for patch in tables {
+            if let Patch::HashTable(l, pairs) = patch {
+                if let Lisp::HashTable(ref table) = *l {
+                    let mut table = table.borrow_mut();
+                    for (k, v) in pairs {
+                        table.insert(self.resolve(k)?, self.resolve(v)?);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Verifies the code blocks read by nodes and the entry points of the
+    // closures, returning the stack depths each block needs.
+    fn verify(&self) -> Result<Depths, Box<dyn Error>> {
+        let mut depths = HashMap::new();
+        for node in &self.nodes {
+            if let Node::Code(ref code) = *node {
+                match verify::depths(code) {
+                    Ok(ds) => depths.insert(&***code as *const [CodeOPInfo], ds),
+                    Err(e) => return self.error(&format!("instruction {}: {}", e.at, e.msg)),
+                };
+            }
+        }
+        for node in &self.nodes {
+            if let Node::Lisp(ref l) = *node {
+                if let Lisp::Closure(_, ref code, pos, _) = **l {
+                    match pos.checked_sub(1).map(|i| &code[i].op) {
+                        Some(CodeOP::CLOSURE(..)) => {}
+                        _ => return self.error(&format!("closure entry {} is not a closure body", pos)),
+                    }
+                }
+            }
+        }
+        Ok(depths)
+    }
+
+    // checks that code can resume at its position with depth values on the
+    // stack of its frame
+    fn resumes(&self,
+               depths: &Depths,
+               code: &(Code, CodePos),
+               depth: usize)
+               -> Result<(), Box<dyn Error>> {
+        match depths[&(&**code.0 as *const [CodeOPInfo])][code.1] {
+            Some(needed) if needed > depth => {
+                self.error(&format!("instruction {} needs {} values on the stack, got {}",
+                                    code.1,
+                                    needed,
+                                    depth))
+            }
+            Some(_) => Ok(()),
+            None => self.error(&format!("instruction {} can not be reached", code.1)),
+        }
+    }
+
+    fn lisp(&mut self, tag: u8) -> Result<Rc<Lisp>, Box<dyn Error>> {
+        let l = match tag {
+            6 => Lisp::List(self.stack()?),
+            7 => {
+                let names = self.names_ref()?;
+                let (code, pos) = self.code_pos()?;
+                let env = self.env()?;
+                Lisp::Closure(names, code, pos, env)
+            }
+            8 => {
+                let name = self.str()?;
+                let arity = self.usize()?;
+                match (self.natives)(&name) {
+                    Some(ref native) if native.arity == arity => Lisp::Native(native.clone()),
+                    Some(_) => return self.error(&format!("native '{}' has a different arity", name)),
+                    None => return self.error(&format!("unknown native '{}'", name)),
+                }
+            }
+            9 => {
+                let car = self.slot()?;
+                let cdr = self.slot()?;
+                let l = Rc::new(Lisp::Cons(RefCell::new(Value::Nil), RefCell::new(Value::Nil)));
+                self.patches.push(Patch::Cons(l.clone(), car, cdr));
+                return Ok(l);
+            }
+            10 => {
+                let slot = self.slot()?;
+                let l = Rc::new(Lisp::Box(RefCell::new(Value::Nil)));
+                self.patches.push(Patch::Box(l.clone(), slot));
+                return Ok(l);
+            }
+            11 => {
+                let n = self.len()?;
+                let slots = self.slots(n)?;
+                let l = Rc::new(Lisp::Vector(RefCell::new(vec![Value::Nil; n])));
+                self.patches.push(Patch::Vector(l.clone(), slots));
+                return Ok(l);
+            }
+            12 => {
+                let n = self.len()?;
+                let mut pairs = Vec::with_capacity(n);
+                for _ in 0..n {
+                    pairs.push((self.slot()?, self.slot()?));
+                }
+                let l = Rc::new(Lisp::HashTable(RefCell::new(HashMap::new())));
+                self.patches.push(Patch::HashTable(l.clone(), pairs));
+                return Ok(l);
+            }
+            tag => return self.error(&format!("bad node tag {}", tag)),
+        };
+        Ok(Rc::new(l))
+    }
+
+    fn op(&mut self) -> Result<CodeOPInfo, Box<dyn Error>> {
+        let info = [self.usize()?, self.usize()?];
+        let op = match self.u8()? {
+            0 => CodeOP::LET(self.sym()?),
+            1 => CodeOP::LD(self.sym()?),
+            2 => CodeOP::SET(self.sym()?),
+            3 => CodeOP::DEF(self.sym()?),
+            4 => CodeOP::LDC(self.value()?),
+            5 => {
+                let names = self.names_ref()?;
+                CodeOP::CLOSURE(names, self.usize()?)
+            }
+            6 => CodeOP::JMP(self.usize()?),
+            7 => CodeOP::JMPF(self.usize()?),
+            8 => CodeOP::ARGS(self.usize()?),
+            9 => CodeOP::READ(self.usize()?),
+            10 => CodeOP::VECTOR(self.usize()?),
+            tag => {
+                match SIMPLE_OPS.get((tag as usize).wrapping_sub(16)) {
+                    Some(op) => op.clone(),
+                    None => return self.error(&format!("bad instruction tag {}", tag)),
+                }
+            }
+        };
+        Ok(CodeOPInfo { info, op })
+    }
+}
+
+fn limit<T: Copy + Into<u64>>(e: &mut Encoder, l: Option<T>) {
+    match l {
+        Some(n) => {
+            e.u8(1);
+            e.u64(n.into());
+        }
+        None => e.u8(0),
+    }
+}
+
+fn read_limit(d: &mut Decoder) -> Result<Option<u64>, Box<dyn Error>> {
+    match d.u8()? {
+        0 => Ok(None),
+        1 => Ok(Some(d.u64()?)),
+        tag => d.error(&format!("bad limit tag {}", tag)),
+    }
+}
+
+impl SECD {
+    // Serializes the registers, globals, pending input, counters and limits.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut e = Encoder::new();
+        for val in &self.stack {
+            e.add_value(val);
+        }
+        for (_, cell) in sorted(&self.env).into_iter().chain(sorted(&self.globals)) {
+            e.add(Node::Cell(cell.clone()));
+        }
+        e.add(Node::Code(self.code.0.clone()));
+        for DumpOP::DumpAP(_, env, code) in &self.dump {
+            for (_, cell) in sorted(env) {
+                e.add(Node::Cell(cell.clone()));
+            }
+            e.add(Node::Code(code.0.clone()));
+        }
+
+        e.header(MAGIC, VERSION);
+        e.nodes();
+        e.stack(&self.stack);
+        e.env(&self.env);
+        e.code_pos(&self.code);
+        e.u32(self.dump.len() as u32);
+        for DumpOP::DumpAP(base, env, code) in &self.dump {
+            e.u64(*base as u64);
+            e.env(env);
+            e.code_pos(code);
+        }
+        e.env(&self.globals);
+        e.str(&self.input);
+        e.u64(self.executed);
+        e.u64(self.allocated);
+        limit(&mut e, self.limits.instructions);
+        limit(&mut e, self.limits.dump_depth.map(|n| n as u64));
+        limit(&mut e, self.limits.stack_size.map(|n| n as u64));
+        limit(&mut e, self.limits.allocations);
+        e.out
+    }
+
+    // Rebuilds a machine from a snapshot that holds no host functions. The
+    // restored machine writes to stdout.
+    pub fn restore(bytes: &[u8]) -> Result<SECD, Box<dyn Error>> {
+        SECD::restore_with(bytes, |_| None)
+    }
+
+    // Like restore, resolving host functions by name.
+    pub fn restore_with<F>(bytes: &[u8], natives: F) -> Result<SECD, Box<dyn Error>>
+        where F: Fn(&str) -> Option<Native>
+    {
+        let mut d = Decoder::new(bytes, "snapshot", &natives);
+        d.header(MAGIC, VERSION)?;
+        d.nodes()?;
+        let depths = d.verify()?;
+        let stack = d.stack()?;
+        let env = d.env()?;
+        let code = d.code_pos()?;
+        let mut dump = Vec::new();
+        // a frame returns to its code with the stack cut back to its base
+        // and the result pushed, above the base of the frame below it
+        let mut below = 0;
+        for _ in 0..d.len()? {
+            let base = d.usize()?;
+            if base < below || base > stack.len() {
+                return d.error(&format!("stack base {} out of range", base));
+            }
+            let env = d.env()?;
+            let code = d.code_pos()?;
+            d.resumes(&depths, &code, base - below + 1)?;
+            dump.push(DumpOP::DumpAP(base, env, code));
+            below = base;
+        }
+        d.resumes(&depths, &code, stack.len() - below)?;
+
+        let mut secd = SECD::new(code.0);
+        secd.code.1 = code.1;
+        secd.stack = stack;
+        secd.env = env;
+        secd.dump = dump;
+        secd.globals = d.env()?;
+        secd.input = d.str()?;
+        secd.executed = d.u64()?;
+        secd.allocated = d.u64()?;
+        secd.limits = Limits {
+            instructions: read_limit(&mut d)?,
+            dump_depth: read_limit(&mut d)?.map(|n| n as usize),
+            stack_size: read_limit(&mut d)?.map(|n| n as usize),
+            allocations: read_limit(&mut d)?,
+        };
+        d.finish()?;
+        Ok(secd)
+    }
+}
@@ -0,0 +1,86 @@
+use std::fmt;
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hasher};
+use std::sync::Mutex;
+
+// An interned identifier. Every distinct name is stored once in a global
+// table, so symbols compare, hash and copy as the id alone and the name is
+// only looked up to print it. Names are never freed, so the table grows with
+// every distinct name a process sees: identifiers in compiled code, fresh
+// names the compiler makes for inlined bindings (reused from form to form),
+// and any symbol in data given to read or eval.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+struct Table {
+    ids: HashMap<&'static str, Symbol>,
+    names: Vec<&'static str>,
+}
+
+static TABLE: Mutex<Option<Table>> = Mutex::new(None);
+
+impl Symbol {
+    pub fn intern(name: &str) -> Symbol {
+        let mut table = TABLE.lock().unwrap();
+        let table = table.get_or_insert_with(|| {
+                                                 Table {
+                                                     ids: HashMap::new(),
+                                                     names: Vec::new(),
+                                                 }
+                                             });
+        if let Some(&sym) = table.ids.get(name) {
+            return sym;
+        }
+
+        let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+        let sym = Symbol(table.names.len() as u32);
+        table.names.push(name);
+        table.ids.insert(name, sym);
+        sym
+    }
+
+    pub fn as_str(self) -> &'static str {
+        TABLE.lock().unwrap().as_ref().unwrap().names[self.0 as usize]
+    }
+}
+
+impl<'a> From<&'a str> for Symbol {
+    fn from(name: &'a str) -> Symbol {
+        Symbol::intern(name)
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+// Symbols are small sequential ids, so tables keyed by them (such as Env) can
+// skip SipHash and just spread the bits.
+#[derive(Default)]
+pub struct SymbolHasher(u64);
+
+impl Hasher for SymbolHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0.rotate_left(5) ^ u64::from(b)).wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
+        }
+    }
+
+    fn write_u32(&mut self, n: u32) {
+        self.0 = (self.0.rotate_left(5) ^ u64::from(n)).wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
+    }
+}
+
+pub type BuildSymbolHasher = BuildHasherDefault<SymbolHasher>;
@@ -0,0 +1,516 @@
+use ir::{Expr, ExprKind, Var};
+use data::{CodeOP, Info, Value};
+use symbol::Symbol;
+
+use std::fmt;
+use std::error::Error;
+use std::collections::{HashMap, HashSet};
+
+// Optional Hindley-Milner type inference over the core IR, run by
+// `secd --typecheck` before compiling. Nil is the empty list of any element
+// type, and a list is a pair of an element and a list, so cons, car and cdr
+// work on lists as well as on pairs used as tuples.
+// Names bound by let, letrec and define are generalized when bound to a
+// lambda, constant or variable, unless set! assigns to them; other expressions
+// may create mutable cells, so their types stay monomorphic. A later form
+// cannot set! a global an earlier one defined with a polymorphic type.
+// Globals the checker has not seen defined, such as native functions, can
+// have any type.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Var(u32),
+    Int,
+    Bool,
+    Char,
+    List(Box<Type>),
+    Pair(Box<Type>, Box<Type>),
+    Fun(Vec<Type>, Box<Type>),
+    Vector(Box<Type>),
+    Box(Box<Type>),
+    Hash(Box<Type>, Box<Type>),
+}
+
+impl Type {
+    fn pair(a: Type, b: Type) -> Type {
+        Type::Pair(Box::new(a), Box::new(b))
+    }
+
+    fn list(a: Type) -> Type {
+        Type::List(Box::new(a))
+    }
+
+    fn vector(a: Type) -> Type {
+        Type::Vector(Box::new(a))
+    }
+
+    fn boxed(a: Type) -> Type {
+        Type::Box(Box::new(a))
+    }
+
+    fn hash(k: Type, v: Type) -> Type {
+        Type::Hash(Box::new(k), Box::new(v))
+    }
+
+    fn children(&self) -> Vec<&Type> {
+        match *self {
+            Type::Pair(ref a, ref b) |
+            Type::Hash(ref a, ref b) => vec![a, b],
+            Type::Fun(ref params, ref ret) => params.iter().chain(Some(&**ret)).collect(),
+            Type::List(ref a) | Type::Vector(ref a) | Type::Box(ref a) => vec![a],
+            _ => Vec::new(),
+        }
+    }
+
+    fn vars(&self, out: &mut Vec<u32>) {
+        match *self {
+            Type::Var(v) => {
+                if !out.contains(&v) {
+                    out.push(v);
+                }
+            }
+            _ => {
+                for t in self.children() {
+                    t.vars(out);
+                }
+            }
+        }
+    }
+}
+
+// Prints types, naming their variables 'a, 'b, ... in order of appearance.
+// Types printed by one Names share variable names.
+struct Names(HashMap<u32, String>);
+
+impl Names {
+    fn show(&mut self, ty: &Type) -> String {
+        match *ty {
+            Type::Var(v) => {
+                let n = self.0.len();
+                self.0
+                    .entry(v)
+                    .or_insert_with(|| if n < 26 {
+                                        format!("'{}", (b'a' + n as u8) as char)
+                                    } else {
+                                        format!("'t{}", n)
+                                    })
+                    .clone()
+            }
+            Type::Int => "int".to_string(),
+            Type::Bool => "bool".to_string(),
+            Type::Char => "char".to_string(),
+            Type::List(ref a) => format!("(list {})", self.show(a)),
+            Type::Pair(ref a, ref b) => format!("(pair {} {})", self.show(a), self.show(b)),
+            Type::Fun(ref params, ref ret) => {
+                let mut s = String::from("(");
+                for p in params {
+                    s += &self.show(p);
+                    s += " ";
+                }
+                s += "-> ";
+                s += &self.show(ret);
+                s + ")"
+            }
+            Type::Vector(ref a) => format!("(vector {})", self.show(a)),
+            Type::Box(ref a) => format!("(box {})", self.show(a)),
+            Type::Hash(ref k, ref v) => format!("(hash {} {})", self.show(k), self.show(v)),
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&Names(HashMap::new()).show(self))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Scheme {
+    vars: Vec<u32>,
+    ty: Type,
+}
+
+impl Scheme {
+    fn mono(ty: Type) -> Scheme {
+        Scheme {
+            vars: Vec::new(),
+            ty,
+        }
+    }
+}
+
+type TypeResult<T> = Result<T, Box<dyn Error>>;
+
+fn error<T>(info: &Info, msg: &str) -> TypeResult<T> {
+    Err(From::from(format!("{}:{}:type error: {}", info[0], info[1], msg)))
+}
+
+// whether binding e can only create a value, never a mutable cell
+fn is_value(e: &Expr) -> bool {
+    matches!(e.kind, ExprKind::Const(_) | ExprKind::Var(_) | ExprKind::Lambda(..))
+}
+
+pub struct TypeChecker {
+    // what each type variable has been unified with, if anything
+    subst: Vec<Option<Type>>,
+    locals: HashMap<u32, Scheme>,
+    globals: HashMap<Symbol, Scheme>,
+    // binders and globals some set! assigns to, which stay monomorphic
+    assigned: HashSet<u32>,
+    assigned_globals: HashSet<Symbol>,
+}
+
+impl Default for TypeChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TypeChecker {
+    pub fn new() -> Self {
+        TypeChecker {
+            subst: Vec::new(),
+            locals: HashMap::new(),
+            globals: HashMap::new(),
+            assigned: HashSet::new(),
+            assigned_globals: HashSet::new(),
+        }
+    }
+
+    // the type of expr, after checking it is used consistently; defines are
+    // remembered for later calls
+    pub fn typecheck(&mut self, expr: &Expr) -> TypeResult<Type> {
+        // a set! may come after the uses of the name it assigns to
+        let (assigned, globals) = (&mut self.assigned, &mut self.assigned_globals);
+        expr.walk(&mut |e| match e.kind {
+                           ExprKind::Set(Var::Bound(b), _) => {
+                               assigned.insert(b.id);
+                           }
+                           ExprKind::Set(Var::Free(name), _) => {
+                               globals.insert(name);
+                           }
+                           _ => {}
+                       });
+        let ty = self.infer(expr)?;
+        Ok(self.zonk(&ty))
+    }
+
+    // the type of nil, and of the forms that return it
+    fn nil(&mut self) -> Type {
+        let a = self.fresh();
+        Type::list(a)
+    }
+
+    fn fresh(&mut self) -> Type {
+        self.subst.push(None);
+        Type::Var(self.subst.len() as u32 - 1)
+    }
+
+    // follows bound variables at the top of ty
+    fn resolve(&self, ty: &Type) -> Type {
+        let mut ty = ty.clone();
+        while let Type::Var(v) = ty {
+            match self.subst[v as usize] {
+                Some(ref t) => ty = t.clone(),
+                None => break,
+            }
+        }
+        ty
+    }
+
+    // Substitutes bound variables everywhere in ty. A pair of an element and
+    // a list of it unifies with that list, so it is shown as the list.
+    fn zonk(&self, ty: &Type) -> Type {
+        match self.resolve(ty) {
+            Type::Pair(a, b) => {
+                match (self.zonk(&a), self.zonk(&b)) {
+                    (a, Type::List(b)) if a == *b => Type::List(b),
+                    (a, b) => Type::pair(a, b),
+                }
+            }
+            Type::List(a) => Type::list(self.zonk(&a)),
+            Type::Fun(params, ret) => {
+                Type::Fun(params.iter().map(|t| self.zonk(t)).collect(),
+                          Box::new(self.zonk(&ret)))
+            }
+            Type::Vector(a) => Type::vector(self.zonk(&a)),
+            Type::Box(a) => Type::boxed(self.zonk(&a)),
+            Type::Hash(k, v) => Type::hash(self.zonk(&k), self.zonk(&v)),
+            ty => ty,
+        }
+    }
+
+    fn occurs(&self, v: u32, ty: &Type) -> bool {
+        match self.resolve(ty) {
+            Type::Var(w) => v == w,
+            ty => ty.children().iter().any(|t| self.occurs(v, t)),
+        }
+    }
+
+    // unifies the type a use expects with the type it got at info
+    fn unify(&mut self, expected: &Type, got: &Type, info: &Info) -> TypeResult<()> {
+        let (a, b) = (self.resolve(expected), self.resolve(got));
+        match (&a, &b) {
+            (&Type::Var(v), &Type::Var(w)) if v == w => Ok(()),
+            (&Type::Var(v), t) | (t, &Type::Var(v)) => {
+                // a value whose cdr has the type of the value itself is a list
+                if let Type::Pair(ref x, ref y) = *t {
+                    if self.resolve(y) == Type::Var(v) && !self.occurs(v, x) {
+                        self.subst[v as usize] = Some(Type::List(x.clone()));
+                        return Ok(());
+                    }
+                }
+                if self.occurs(v, t) {
+                    let mut names = Names(HashMap::new());
+                    let msg = format!("infinite type {} = {}",
+                                      names.show(&Type::Var(v)),
+                                      names.show(&self.zonk(t)));
+                    return error(info, &msg);
+                }
+                self.subst[v as usize] = Some(t.clone());
+                Ok(())
+            }
+            (&Type::Pair(ref a1, ref b1), &Type::Pair(ref a2, ref b2)) |
+            (&Type::Hash(ref a1, ref b1), &Type::Hash(ref a2, ref b2)) => {
+                self.unify(a1, a2, info)?;
+                self.unify(b1, b2, info)
+            }
+            (Type::Pair(x, y), Type::List(a)) => {
+                self.unify(x, a, info)?;
+                self.unify(y, &b, info)
+            }
+            (Type::List(a), Type::Pair(x, y)) => {
+                self.unify(a, x, info)?;
+                self.unify(&Type::list((**a).clone()), y, info)
+            }
+            (&Type::List(ref a1), &Type::List(ref a2)) |
+            (&Type::Vector(ref a1), &Type::Vector(ref a2)) |
+            (&Type::Box(ref a1), &Type::Box(ref a2)) => self.unify(a1, a2, info),
+            (Type::Fun(p1, r1), Type::Fun(p2, r2)) if p1.len() == p2.len() => {
+                for (t1, t2) in p1.iter().zip(p2) {
+                    self.unify(t1, t2, info)?;
+                }
+                self.unify(r1, r2, info)
+            }
+            _ if a == b => Ok(()),
+            _ => {
+                let mut names = Names(HashMap::new());
+                let msg = format!("expected {}, got {}",
+                                  names.show(&self.zonk(expected)),
+                                  names.show(&self.zonk(got)));
+                error(info, &msg)
+            }
+        }
+    }
+
+    fn instantiate(&mut self, scheme: &Scheme) -> Type {
+        let mut vars = HashMap::new();
+        for &v in &scheme.vars {
+            vars.insert(v, self.fresh());
+        }
+        self.substitute(&scheme.ty, &vars)
+    }
+
+    fn substitute(&self, ty: &Type, vars: &HashMap<u32, Type>) -> Type {
+        match self.resolve(ty) {
+            Type::Var(v) => vars.get(&v).cloned().unwrap_or(Type::Var(v)),
+            Type::Pair(a, b) => Type::pair(self.substitute(&a, vars), self.substitute(&b, vars)),
+            Type::Fun(params, ret) => {
+                Type::Fun(params.iter().map(|t| self.substitute(t, vars)).collect(),
+                          Box::new(self.substitute(&ret, vars)))
+            }
+            Type::List(a) => Type::list(self.substitute(&a, vars)),
+            Type::Vector(a) => Type::vector(self.substitute(&a, vars)),
+            Type::Box(a) => Type::boxed(self.substitute(&a, vars)),
+            Type::Hash(k, v) => Type::hash(self.substitute(&k, vars), self.substitute(&v, vars)),
+            ty => ty,
+        }
+    }
+
+    // quantifies the variables of ty that nothing in scope mentions
+    fn generalize(&self, ty: &Type) -> Scheme {
+        let ty = self.zonk(ty);
+        let mut env = Vec::new();
+        for scheme in self.locals.values().chain(self.globals.values()) {
+            self.zonk(&scheme.ty).vars(&mut env);
+        }
+        let env: HashSet<u32> = env.into_iter().collect();
+
+        let mut vars = Vec::new();
+        ty.vars(&mut vars);
+        vars.retain(|v| !env.contains(v));
+        Scheme { vars, ty }
+    }
+
+    fn bind(&mut self, var: Var, e: &Expr, ty: &Type) -> Scheme {
+        let assigned = match var {
+            Var::Bound(b) => self.assigned.contains(&b.id),
+            Var::Free(name) => self.assigned_globals.contains(&name),
+        };
+        if is_value(e) && !assigned {
+            self.generalize(ty)
+        } else {
+            Scheme::mono(ty.clone())
+        }
+    }
+
+    fn infer(&mut self, e: &Expr) -> TypeResult<Type> {
+        match e.kind {
+            ExprKind::Const(ref val) => {
+                Ok(match *val {
+                       Value::Int(_) => Type::Int,
+                       Value::Char(_) => Type::Char,
+                       Value::True | Value::False => Type::Bool,
+                       Value::Nil => self.nil(),
+                       _ => self.fresh(),
+                   })
+            }
+            ExprKind::Var(Var::Bound(b)) => {
+                match self.locals.get(&b.id).cloned() {
+                    Some(scheme) => Ok(self.instantiate(&scheme)),
+                    None => Ok(self.fresh()),
+                }
+            }
+            ExprKind::Var(Var::Free(name)) => {
+                match self.globals.get(&name).cloned() {
+                    Some(scheme) => Ok(self.instantiate(&scheme)),
+                    None => Ok(self.fresh()),
+                }
+            }
+            ExprKind::Lambda(ref params, ref body) => {
+                let mut tys = Vec::new();
+                for p in params {
+                    let t = self.fresh();
+                    self.locals.insert(p.id, Scheme::mono(t.clone()));
+                    tys.push(t);
+                }
+                let ret = self.infer(body)?;
+                for p in params {
+                    self.locals.remove(&p.id);
+                }
+                Ok(Type::Fun(tys, Box::new(ret)))
+            }
+            ExprKind::App(ref fun, ref args) => {
+                let mut tys = Vec::new();
+                for arg in args {
+                    tys.push(self.infer(arg)?);
+                }
+                let tf = self.infer(fun)?;
+                match self.resolve(&tf) {
+                    Type::Fun(params, ret) => {
+                        if params.len() != args.len() {
+                            return error(&fun.info,
+                                         &format!("expected {} args, got {}",
+                                                  params.len(),
+                                                  args.len()));
+                        }
+                        for ((p, t), arg) in params.iter().zip(&tys).zip(args) {
+                            self.unify(p, t, &arg.info)?;
+                        }
+                        Ok(*ret)
+                    }
+                    tf => {
+                        let ret = self.fresh();
+                        self.unify(&tf, &Type::Fun(tys, Box::new(ret.clone())), &fun.info)?;
+                        Ok(ret)
+                    }
+                }
+            }
+            ExprKind::If(ref cond, ref then, ref else_) => {
+                let tc = self.infer(cond)?;
+                self.unify(&Type::Bool, &tc, &cond.info)?;
+                let tt = self.infer(then)?;
+                let te = self.infer(else_)?;
+                self.unify(&tt, &te, &else_.info)?;
+                Ok(tt)
+            }
+            ExprKind::Let(b, ref expr, ref body) => {
+                let te = self.infer(expr)?;
+                let scheme = self.bind(Var::Bound(b), expr, &te);
+                self.locals.insert(b.id, scheme);
+                let r = self.infer(body);
+                self.locals.remove(&b.id);
+                r
+            }
+            ExprKind::LetRec(b, ref expr, ref body) => {
+                let tv = self.fresh();
+                self.locals.insert(b.id, Scheme::mono(tv.clone()));
+                let te = self.infer(expr)?;
+                self.unify(&tv, &te, &expr.info)?;
+                self.locals.remove(&b.id);
+
+                let scheme = self.bind(Var::Bound(b), expr, &te);
+                self.locals.insert(b.id, scheme);
+                let r = self.infer(body);
+                self.locals.remove(&b.id);
+                r
+            }
+            ExprKind::Define(name, ref expr) => {
+                let tv = self.fresh();
+                self.globals.insert(name, Scheme::mono(tv.clone()));
+                let te = self.infer(expr)?;
+                self.unify(&tv, &te, &expr.info)?;
+                self.globals.remove(&name);
+                let scheme = self.bind(Var::Free(name), expr, &te);
+                self.globals.insert(name, scheme);
+                Ok(self.nil())
+            }
+            ExprKind::Set(var, ref expr) => {
+                // defined polymorphic by an earlier form, before this set! was seen
+                if let Var::Free(name) = var {
+                    if self.globals.get(&name).is_some_and(|s| !s.vars.is_empty()) {
+                        return error(&e.info,
+                                     &format!("cannot set! '{}', whose type is polymorphic", name));
+                    }
+                }
+                let tv = self.infer(&Expr::new(e.info, ExprKind::Var(var)))?;
+                let te = self.infer(expr)?;
+                self.unify(&tv, &te, &expr.info)?;
+                Ok(self.nil())
+            }
+            ExprKind::PrimOp(ref op, ref args) => {
+                let (params, ret) = self.signature(op, args.len());
+                for (p, arg) in params.iter().zip(args) {
+                    let t = self.infer(arg)?;
+                    self.unify(p, &t, &arg.info)?;
+                }
+                Ok(ret)
+            }
+        }
+    }
+
+    // argument and result types of a primitive applied to n arguments
+    fn signature(&mut self, op: &CodeOP, n: usize) -> (Vec<Type>, Type) {
+        let a = self.fresh();
+        let b = self.fresh();
+        match *op {
+            CodeOP::ADD | CodeOP::SUB => (vec![Type::Int, Type::Int], Type::Int),
+            CodeOP::EQ => (vec![a, b], Type::Bool),
+            CodeOP::CONS => (vec![a.clone(), b.clone()], Type::pair(a, b)),
+            CodeOP::CAR => (vec![Type::pair(a.clone(), b)], a),
+            CodeOP::CDR => (vec![Type::pair(a, b.clone())], b),
+            CodeOP::SETCAR => (vec![Type::pair(a.clone(), b), a], self.nil()),
+            CodeOP::SETCDR => (vec![Type::pair(a, b.clone()), b], self.nil()),
+            CodeOP::PUTS | CodeOP::WRITE => (vec![a.clone()], a),
+            CodeOP::BOX => (vec![a.clone()], Type::boxed(a)),
+            CodeOP::UNBOX => (vec![Type::boxed(a.clone())], a),
+            CodeOP::SETBOX => (vec![Type::boxed(a.clone()), a], self.nil()),
+            CodeOP::VECTOR(n) => (vec![a.clone(); n], Type::vector(a)),
+            CodeOP::MKVECTOR => (vec![Type::Int, a.clone()], Type::vector(a)),
+            CodeOP::VECTORREF => (vec![Type::vector(a.clone()), Type::Int], a),
+            CodeOP::VECTORSET => (vec![Type::vector(a.clone()), Type::Int, a], self.nil()),
+            CodeOP::VECTORLEN => (vec![Type::vector(a)], Type::Int),
+            CodeOP::MKHASH => (vec![], Type::hash(a, b)),
+            CodeOP::HASHREF => (vec![Type::hash(a.clone(), b.clone()), a], b),
+            CodeOP::HASHSET => (vec![Type::hash(a.clone(), b.clone()), a, b], self.nil()),
+            CodeOP::HASHHAS => (vec![Type::hash(a.clone(), b), a], Type::Bool),
+            CodeOP::HASHREMOVE => (vec![Type::hash(a.clone(), b), a], self.nil()),
+            CodeOP::HASHCOUNT => (vec![Type::hash(a, b)], Type::Int),
+            CodeOP::CHAR2INT => (vec![Type::Char], Type::Int),
+            CodeOP::INT2CHAR => (vec![Type::Int], Type::Char),
+            CodeOP::CHAREQ | CodeOP::CHARLT => (vec![Type::Char, Type::Char], Type::Bool),
+            // read, eval and hash-keys produce data of any shape
+            _ => ((0..n).map(|_| self.fresh()).collect(), a),
+        }
+    }
+}
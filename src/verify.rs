@@ -0,0 +1,152 @@
+use data::{CodeOP, CodeOPInfo};
+
+// Structural checks for code that did not come from the compiler, such as
+// bytecode files and assembler input, so that running or optimizing it
+// cannot crash the machine:
+//
+// - jump and closure offsets stay within the code and within the closure
+//   body they appear in, and never land inside a nested body;
+// - closure bodies end in RET rather than running into the code after them;
+// - no instruction pops more values than the stack holds on any path to it.
+//
+// Types are not checked; the machine reports those errors when it runs.
+
+pub struct Invalid {
+    // index of the offending instruction
+    pub at: usize,
+    pub msg: String,
+}
+
+type VerifyResult = Result<(), Invalid>;
+
+fn invalid(at: usize, msg: String) -> VerifyResult {
+    Err(Invalid { at, msg })
+}
+
+// values an instruction pops and pushes
+fn effect(op: &CodeOP) -> (usize, usize) {
+    match *op {
+        CodeOP::LD(_) | CodeOP::LDC(_) | CodeOP::CLOSURE(..) | CodeOP::MKHASH => (0, 1),
+        CodeOP::LET(_) | CodeOP::JMPF(_) => (1, 0),
+        CodeOP::JMP(_) => (0, 0),
+        CodeOP::READ(0) => (0, 1),
+        CodeOP::ARGS(n) | CodeOP::VECTOR(n) => (n, 1),
+        CodeOP::SET(_) | CodeOP::DEF(_) | CodeOP::RET | CodeOP::PUTS | CodeOP::WRITE |
+        CodeOP::READ(_) | CodeOP::EVAL | CodeOP::CAR | CodeOP::CDR | CodeOP::BOX |
+        CodeOP::UNBOX | CodeOP::VECTORLEN | CodeOP::HASHKEYS | CodeOP::HASHCOUNT |
+        CodeOP::CHAR2INT | CodeOP::INT2CHAR => (1, 1),
+        CodeOP::AP | CodeOP::RAP | CodeOP::EQ | CodeOP::ADD | CodeOP::SUB | CodeOP::CONS |
+        CodeOP::SETCAR | CodeOP::SETCDR | CodeOP::SETBOX | CodeOP::MKVECTOR |
+        CodeOP::VECTORREF | CodeOP::HASHREF | CodeOP::HASHHAS | CodeOP::HASHREMOVE |
+        CodeOP::CHAREQ | CodeOP::CHARLT => (2, 1),
+        CodeOP::VECTORSET | CodeOP::HASHSET => (3, 1),
+    }
+}
+
+pub fn verify(code: &[CodeOPInfo]) -> VerifyResult {
+    depths(code).map(|_| ())
+}
+
+// Verifies code and returns, for each position up to its end, the least stack
+// depth on arrival there relative to the start of the program or closure body
+// it belongs to; None where nothing arrives.
+pub fn depths(code: &[CodeOPInfo]) -> Result<Vec<Option<usize>>, Invalid> {
+    let mut out = vec![None; code.len() + 1];
+    body(code, 0, code.len(), false, &mut out)?;
+    Ok(out)
+}
+
+// Checks code[start..end], a whole program or a closure body, and records its
+// depths in out. Jumps only go forward, so one pass sees every way into an
+// instruction before it.
+fn body(code: &[CodeOPInfo],
+        start: usize,
+        end: usize,
+        closure: bool,
+        out: &mut [Option<usize>])
+        -> VerifyResult {
+    let what = if closure { "its closure body" } else { "the code" };
+    // the least stack depth on arrival at start + i, where anything arrives
+    let mut depths: Vec<Option<usize>> = vec![None; end - start + 1];
+    depths[0] = Some(0);
+    // whether start + i begins an instruction of this body
+    let mut starts = vec![false; end - start + 1];
+    starts[end - start] = true;
+
+    let mut i = start;
+    while i < end {
+        starts[i - start] = true;
+        let op = &code[i].op;
+        let next = match *op {
+            CodeOP::CLOSURE(_, n) => {
+                match (i + 1).checked_add(n) {
+                    Some(t) if t <= end => {
+                        body(code, i + 1, t, true, out)?;
+                        t
+                    }
+                    _ => {
+                        return invalid(i,
+                                       format!("closure body of {} instructions runs past the end of {}",
+                                               n,
+                                               what))
+                    }
+                }
+            }
+            _ => i + 1,
+        };
+        let target = match *op {
+            CodeOP::JMP(n) | CodeOP::JMPF(n) => {
+                // a body is left by RET, not by jumping to its end
+                match next.checked_add(n) {
+                    Some(t) if t < end || t == end && !closure => Some(t),
+                    _ => return invalid(i, format!("jump of {} runs past the end of {}", n, what)),
+                }
+            }
+            _ => None,
+        };
+
+        if let Some(depth) = depths[i - start] {
+            let (pops, pushes) = effect(op);
+            if depth < pops {
+                return invalid(i,
+                               format!("{:?} needs {} values on the stack, got {}",
+                                       op,
+                                       pops,
+                                       depth));
+            }
+            let depth = depth - pops + pushes;
+
+            if let Some(t) = target {
+                arrive(&mut depths, t - start, depth);
+            }
+            match *op {
+                CodeOP::JMP(_) | CodeOP::RET => {}
+                _ => arrive(&mut depths, next - start, depth),
+            }
+        }
+        i = next;
+    }
+
+    // a jump may skip over a nested body, but not into one
+    for (i, op) in code.iter().enumerate().take(end).skip(start) {
+        if let CodeOP::JMP(n) | CodeOP::JMPF(n) = op.op {
+            if starts[i - start] && !starts[i + 1 + n - start] {
+                return invalid(i, format!("jump of {} lands inside a closure body", n));
+            }
+        }
+    }
+
+    if closure && depths[end - start].is_some() {
+        return invalid(end - 1, "closure body does not end in RET".to_string());
+    }
+    for (i, &depth) in depths.iter().enumerate() {
+        if starts[i] && (i < end - start || !closure) {
+            out[start + i] = depth;
+        }
+    }
+    Ok(())
+}
+
+fn arrive(depths: &mut [Option<usize>], at: usize, depth: usize) {
+    depths[at] = Some(depths[at].map_or(depth, |d| d.min(depth)));
+}
@@ -1,297 +1,1060 @@
 
 use data::*;
+use printer;
+use output::Output;
+use limits::{Limits, Limit, LimitExceeded};
+use datum::{ast_to_lisp, lisp_to_ast, allocations};
+use parser::Parser;
+use compiler::Compiler;
+use symbol::Symbol;
 
 use std::rc::Rc;
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::error::Error;
 use std::mem;
+use std::io;
 
-type VMResult = Result<(), Box<Error>>;
+type VMResult = Result<(), Box<dyn Error>>;
+
+// Each call runs the machine on the native stack, so nesting is capped
+// whatever the limits say.
+pub const MAX_CALLS: usize = 100;
 
 impl SECD {
     pub fn new(c: Code) -> SECD {
         SECD {
             stack: Vec::new(),
-            env: HashMap::new(),
+            env: Env::default(),
             code: (c, 0),
             dump: Vec::new(),
+            globals: Env::default(),
+            input: String::new(),
+            output: Output::stdout(),
+            limits: Limits::default(),
+            executed: 0,
+            allocated: 0,
+            suspended: (0, 0),
+            calls: 0,
         }
     }
 
-    fn error(&self, info: &Info, msg: &str) -> VMResult {
+    fn error<T>(&self, info: &Info, msg: &str) -> Result<T, Box<dyn Error>> {
         Err(From::from(format!("{}:{}:vm error: {}", info[0], info[1], msg)))
     }
 
-    pub fn run(&mut self) -> Result<Rc<Lisp>, Box<Error>> {
+    pub fn run(&mut self) -> Result<Value, Box<dyn Error>> {
         self.run_()?;
-        Ok(self.stack.last().unwrap().clone())
+        Ok(self.stack.last().cloned().unwrap_or(Value::Nil))
     }
 
-    fn run_(&mut self) -> VMResult {
-        while self.code.0.len() > self.code.1 {
-            let CodeOPInfo{ref op, ref info} = self.code.0.clone()[self.code.1];
-            self.code.1 += 1;
-            match op {
-                &CodeOP::LET(ref id) => {
-                    self.run_let(info, id)?;
+    // Calls a closure or native function to completion and returns its value.
+    // The machine state of the caller is saved and restored around the call,
+    // so this is safe to use from inside a native function; the frames and
+    // stack of the callers count toward the dump depth and stack size limits,
+    // and at most MAX_CALLS calls can be in progress.
+    // A closure runs in its own environment, as AP runs it, so one bound by
+    // letrec cannot call itself and is rejected.
+    pub fn call(&mut self, f: &Value, args: &[Value]) -> Result<Value, Box<dyn Error>> {
+        match f.as_lisp() {
+            Some(&Lisp::Closure(ref names, ref code, pos, ref env)) => {
+                if names.len() != args.len() {
+                    return Err(From::from(format!("vm error: call: expected {} args, got {}",
+                                                  names.len(),
+                                                  args.len())));
                 }
-
-                &CodeOP::LD(ref id) => {
-                    self.run_ld(info, id)?;
+                if let Some(id) = self.unbound_in(names, code, pos, env) {
+                    return Err(From::from(format!("vm error: call: closure refers to '{}', which is \
+                                                   bound outside it, such as by letrec",
+                                                  id.as_str().split('\'').next().unwrap())));
                 }
 
-                &CodeOP::LDC(ref lisp) => {
-                    self.run_ldc(info, lisp)?;
+                if self.calls >= MAX_CALLS {
+                    return Err(From::from(format!("vm error: call: more than {} nested calls",
+                                                  MAX_CALLS)));
                 }
 
-                &CodeOP::LDF(ref names, ref code) => {
-                    self.run_ldf(info, names, code)?;
+                let info = code.get(pos).map(|op| op.info).unwrap_or([0, 0]);
+                self.alloc(&info, names.len() as u64)?;
+                let mut env = env.clone();
+                for (name, val) in names.iter().zip(args) {
+                    env.insert(*name, Rc::new(RefCell::new(val.clone())));
                 }
 
-                &CodeOP::RET => {
-                    self.run_ret(info)?;
-                }
+                // RET of the closure body lands on an empty continuation
+                let done: Code = Rc::new(Vec::new().into_boxed_slice());
+                let stack = mem::take(&mut self.stack);
+                let env = mem::replace(&mut self.env, env);
+                let code = mem::replace(&mut self.code, (code.clone(), pos));
+                let dump = mem::replace(&mut self.dump,
+                                        vec![DumpOP::DumpAP(0, Env::default(), (done, 0))]);
+                let suspended = self.suspended;
+                self.suspended = (suspended.0 + dump.len(), suspended.1 + stack.len());
+                self.calls += 1;
 
-                &CodeOP::AP => {
-                    self.run_ap(info)?;
-                }
+                let r = self.check_limits(&info).and_then(|_| self.run_());
+                let val = self.stack.pop();
 
-                &CodeOP::RAP => {
-                    self.run_rap(info)?;
-                }
+                self.stack = stack;
+                self.env = env;
+                self.code = code;
+                self.dump = dump;
+                self.suspended = suspended;
+                self.calls -= 1;
 
-                &CodeOP::ARGS(n) => {
-                    self.run_args(info, n)?;
-                }
+                r?;
+                Ok(val.unwrap())
+            }
 
-                &CodeOP::PUTS => {
-                    self.run_puts(info)?;
+            Some(Lisp::Native(native)) => {
+                if native.arity != args.len() {
+                    return Err(From::from(format!("vm error: {}: expected {} args, got {}",
+                                                  native.name,
+                                                  native.arity,
+                                                  args.len())));
                 }
 
-                &CodeOP::SEL(ref t, ref f) => {
-                    self.run_sel(info, t, f)?;
-                }
+                (native.fun)(self, args)
+            }
 
-                &CodeOP::JOIN => {
-                    self.run_join(info)?;
-                }
+            _ => Err(From::from(format!("vm error: call: expected Closure, got {}", f))),
+        }
+    }
 
-                &CodeOP::EQ => {
-                    self.run_eq(info)?;
-                }
+    // A name the body of a closure at pos loads without binding it, having
+    // it in the closure's environment or finding it among the globals.
+    fn unbound_in(&self, params: &[Symbol], code: &Code, pos: CodePos, env: &Env) -> Option<Symbol> {
+        let len = match pos.checked_sub(1).and_then(|i| code.get(i)) {
+            Some(&CodeOPInfo { op: CodeOP::CLOSURE(_, n), .. }) => n,
+            _ => return None,
+        };
+        let body = code.get(pos..pos + len)?;
+        let mut bound: Vec<Symbol> = params.to_vec();
+        for op in body {
+            match op.op {
+                CodeOP::LET(id) | CodeOP::DEF(id) => bound.push(id),
+                CodeOP::CLOSURE(ref names, _) => bound.extend(names.iter()),
+                _ => {}
+            }
+        }
+        body.iter().find_map(|op| match op.op {
+            CodeOP::LD(id) if !bound.contains(&id) && !env.contains_key(&id) &&
+                              !self.globals.contains_key(&id) => Some(id),
+            _ => None,
+        })
+    }
 
-                &CodeOP::ADD => {
-                    self.run_add(info)?;
-                }
+    pub fn is_finished(&self) -> bool {
+        self.code.0.len() <= self.code.1
+    }
 
-                &CodeOP::SUB => {
-                    self.run_sub(info)?;
-                }
+    // executes a single instruction
+    pub fn step(&mut self) -> Result<Status, Box<dyn Error>> {
+        self.run_n(1)?;
+        Ok(self.status())
+    }
 
-                &CodeOP::CONS => {
-                    self.run_cons(info)?;
-                }
+    // executes at most n instructions, so a host can interleave machines
+    pub fn run_for(&mut self, n: u64) -> Result<Status, Box<dyn Error>> {
+        self.run_n(n)?;
+        Ok(self.status())
+    }
 
-                &CodeOP::CAR => {
-                    self.run_car(info)?;
-                }
+    fn status(&self) -> Status {
+        if self.is_finished() {
+            Status::Finished(self.stack.last().cloned().unwrap_or(Value::Nil))
+        } else {
+            Status::Paused
+        }
+    }
 
-                &CodeOP::CDR => {
-                    self.run_cdr(info)?;
-                }
+    fn run_(&mut self) -> VMResult {
+        self.run_n(u64::MAX)
+    }
+
+    // Instructions are borrowed from a local handle on the current code, which
+    // only needs refreshing when a call or return switches to other code.
+    fn run_n(&mut self, n: u64) -> VMResult {
+        let mut code = self.code.0.clone();
+        for _ in 0..n {
+            if !Rc::ptr_eq(&code, &self.code.0) {
+                code = self.code.0.clone();
+            }
+            match code.get(self.code.1) {
+                Some(op) => self.exec(op)?,
+                None => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn exec(&mut self, op: &CodeOPInfo) -> VMResult {
+        let CodeOPInfo { ref op, ref info } = *op;
+        if let Some(max) = self.limits.instructions {
+            if self.executed >= max {
+                return self.limit_exceeded(info, Limit::Instructions, max);
+            }
+        }
+        self.executed += 1;
+        self.code.1 += 1;
+        match op {
+            CodeOP::LET(id) => {
+                self.run_let(info, *id)?;
+            }
+
+            CodeOP::LD(id) => {
+                self.run_ld(info, *id)?;
+            }
+
+            CodeOP::SET(id) => {
+                self.run_set(info, *id)?;
+            }
+
+            CodeOP::DEF(id) => {
+                self.run_def(info, *id)?;
+            }
+
+            CodeOP::LDC(val) => {
+                self.run_ldc(info, val)?;
+            }
+
+            CodeOP::CLOSURE(names, n) => {
+                self.run_closure(info, names, *n)?;
+            }
+
+            CodeOP::RET => {
+                self.run_ret(info)?;
+            }
+
+            CodeOP::AP => {
+                self.run_ap(info)?;
+            }
+
+            CodeOP::RAP => {
+                self.run_rap(info)?;
+            }
+
+            CodeOP::ARGS(n) => {
+                self.run_args(info, *n)?;
+            }
+
+            CodeOP::PUTS => {
+                self.run_puts(info)?;
+            }
+
+            CodeOP::WRITE => {
+                self.run_write(info)?;
+            }
+
+            CodeOP::READ(n) => {
+                self.run_read(info, *n)?;
+            }
+
+            CodeOP::EVAL => {
+                self.run_eval(info)?;
+            }
+
+            CodeOP::JMP(n) => {
+                self.run_jmp(info, *n)?;
+            }
+
+            CodeOP::JMPF(n) => {
+                self.run_jmpf(info, *n)?;
+            }
+
+            CodeOP::EQ => {
+                self.run_eq(info)?;
+            }
+
+            CodeOP::ADD => {
+                self.run_add(info)?;
+            }
+
+            CodeOP::SUB => {
+                self.run_sub(info)?;
+            }
+
+            CodeOP::CONS => {
+                self.run_cons(info)?;
+            }
+
+            CodeOP::CAR => {
+                self.run_car(info)?;
+            }
+
+            CodeOP::CDR => {
+                self.run_cdr(info)?;
+            }
+
+            CodeOP::SETCAR => {
+                self.run_setcar(info)?;
+            }
+
+            CodeOP::SETCDR => {
+                self.run_setcdr(info)?;
+            }
+
+            CodeOP::BOX => {
+                self.run_box(info)?;
+            }
+
+            CodeOP::UNBOX => {
+                self.run_unbox(info)?;
+            }
+
+            CodeOP::SETBOX => {
+                self.run_setbox(info)?;
+            }
+
+            CodeOP::VECTOR(n) => {
+                self.run_vector(info, *n)?;
+            }
+
+            CodeOP::MKVECTOR => {
+                self.run_mkvector(info)?;
+            }
+
+            CodeOP::VECTORREF => {
+                self.run_vectorref(info)?;
+            }
+
+            CodeOP::VECTORSET => {
+                self.run_vectorset(info)?;
+            }
+
+            CodeOP::VECTORLEN => {
+                self.run_vectorlen(info)?;
+            }
+
+            CodeOP::MKHASH => {
+                self.run_mkhash(info)?;
+            }
+
+            CodeOP::HASHREF => {
+                self.run_hashref(info)?;
+            }
+
+            CodeOP::HASHSET => {
+                self.run_hashset(info)?;
+            }
+
+            CodeOP::HASHHAS => {
+                self.run_hashhas(info)?;
+            }
+
+            CodeOP::HASHREMOVE => {
+                self.run_hashremove(info)?;
+            }
+
+            CodeOP::HASHKEYS => {
+                self.run_hashkeys(info)?;
+            }
+
+            CodeOP::HASHCOUNT => {
+                self.run_hashcount(info)?;
+            }
+
+            CodeOP::CHAR2INT => {
+                self.run_char2int(info)?;
+            }
+
+            CodeOP::INT2CHAR => {
+                self.run_int2char(info)?;
+            }
+
+            CodeOP::CHAREQ => {
+                self.run_chareq(info)?;
+            }
+
+            CodeOP::CHARLT => {
+                self.run_charlt(info)?;
+            }
+        }
+
+        self.check_limits(info)
+    }
+
+    fn limit_exceeded(&self, info: &Info, limit: Limit, max: u64) -> VMResult {
+        Err(Box::new(LimitExceeded {
+                         limit,
+                         max,
+                         info: *info,
+                     }))
+    }
+
+    fn check_limits(&self, info: &Info) -> VMResult {
+        if let Some(max) = self.limits.dump_depth {
+            if self.dump.len() + self.suspended.0 > max {
+                return self.limit_exceeded(info, Limit::DumpDepth, max as u64);
+            }
+        }
+
+        if let Some(max) = self.limits.stack_size {
+            if self.stack.len() + self.suspended.1 > max {
+                return self.limit_exceeded(info, Limit::StackSize, max as u64);
             }
         }
 
         Ok(())
     }
 
+    // accounts for n new allocations
+    fn alloc(&mut self, info: &Info, n: u64) -> VMResult {
+        self.allocated += n;
+        match self.limits.allocations {
+            Some(max) if self.allocated > max => self.limit_exceeded(info, Limit::Allocations, max),
+            _ => Ok(()),
+        }
+    }
+
 
-    fn run_let(&mut self, _: &Info, id: &str) -> VMResult {
+    fn run_let(&mut self, info: &Info, id: Symbol) -> VMResult {
+        self.alloc(info, 1)?;
         let expr = self.stack.pop().unwrap();
-        self.env.insert(id.to_string(), expr);
+        self.env.insert(id, Rc::new(RefCell::new(expr)));
         Ok(())
     }
 
-    fn run_ld(&mut self, _: &Info, id: &str) -> VMResult {
-        let expr = self.env.get(id).unwrap();
-        self.stack.push(expr.clone());
+    // local bindings shadow globals, which are looked up at run time
+    fn lookup(&self, id: Symbol) -> Option<&Rc<RefCell<Value>>> {
+        self.env.get(&id).or_else(|| self.globals.get(&id))
+    }
+
+    fn run_ld(&mut self, info: &Info, id: Symbol) -> VMResult {
+        let expr = match self.lookup(id) {
+            Some(cell) => cell.borrow().clone(),
+            None => return self.error(info, &format!("LD: unbound variable '{}'", id)),
+        };
+        self.stack.push(expr);
         Ok(())
     }
 
-    fn run_ldc(&mut self, _: &Info, lisp: &Rc<Lisp>) -> VMResult {
-        self.stack.push(lisp.clone());
+    fn run_def(&mut self, info: &Info, id: Symbol) -> VMResult {
+        self.alloc(info, 1)?;
+        let expr = self.stack.pop().unwrap();
+        self.globals.insert(id, Rc::new(RefCell::new(expr)));
+        self.stack.push(Value::Nil);
         Ok(())
     }
 
-    fn run_ldf(&mut self, _: &Info, names: &Rc<Box<[String]>>, code: &Code) -> VMResult {
-        self.stack
-            .push(Rc::new(Lisp::Closure(names.clone(), code.clone(), self.env.clone())));
+    fn run_set(&mut self, info: &Info, id: Symbol) -> VMResult {
+        let val = self.stack.pop().unwrap();
+        match self.lookup(id) {
+            Some(cell) => {
+                *cell.borrow_mut() = val;
+                self.stack.push(Value::Nil);
+                Ok(())
+            }
+
+            None => self.error(info, &format!("SET: unbound variable '{}'", id)),
+        }
+    }
+
+    fn run_ldc(&mut self, _: &Info, val: &Value) -> VMResult {
+        self.stack.push(val.clone());
         Ok(())
     }
 
-    fn run_ap(&mut self, info: &Info) -> VMResult {
-        match *self.stack.pop().unwrap() {
-            Lisp::Closure(ref names, ref code, ref env) => {
-                match *self.stack.pop().unwrap() {
-                    Lisp::List(ref vals) => {
-                        let mut env = env.clone();
-                        for i in 0..names.len() {
-                            env.insert(names[i].clone(), vals[i].clone());
-                        }
+    // the body starts at the next instruction and is n long
+    fn run_closure(&mut self, info: &Info, names: &Rc<Box<[Symbol]>>, n: usize) -> VMResult {
+        self.alloc(info, 1)?;
+        let closure = Lisp::Closure(names.clone(), self.code.0.clone(), self.code.1, self.env.clone());
+        self.stack.push(Value::new(closure));
+        self.code.1 += n;
+        Ok(())
+    }
 
-                        let stack = mem::replace(&mut self.stack, Vec::new());
-                        let env = mem::replace(&mut self.env, env);
-                        let code = mem::replace(&mut self.code, (code.clone(), 0));
+    // The environment and entry point of a call to a closure. The closure's
+    // environment and the argument list are reused rather than copied when
+    // nothing else holds them.
+    fn enter(&mut self, info: &Info, name: &str, f: Rc<Lisp>, args: Value)
+             -> Result<(Env, (Code, CodePos)), Box<dyn Error>> {
+        let vals = match args {
+            Value::Ref(args) => {
+                match Rc::try_unwrap(args) {
+                    Ok(Lisp::List(ref mut vals)) => mem::take(vals),
+                    Ok(_) => return self.error(info, &format!("{}: expected List", name)),
+                    Err(args) => {
+                        match *args {
+                            Lisp::List(ref vals) => vals.clone(),
+                            _ => return self.error(info, &format!("{}: expected List", name)),
+                        }
+                    }
+                }
+            }
+            _ => return self.error(info, &format!("{}: expected List", name)),
+        };
 
-                        self.dump.push(DumpOP::DumpAP(stack, env, code));
+        let (names, code, mut env) = match Rc::try_unwrap(f) {
+            Ok(mut f) => {
+                match f {
+                    Lisp::Closure(ref names, ref code, pos, ref mut env) => {
+                        (names.clone(), (code.clone(), pos), mem::take(env))
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            Err(f) => {
+                match *f {
+                    Lisp::Closure(ref names, ref code, pos, ref env) => {
+                        (names.clone(), (code.clone(), pos), env.clone())
                     }
-                    _ => return self.error(&info, "AP: expected List"),
+                    _ => unreachable!(),
+                }
+            }
+        };
+
+        if names.len() != vals.len() {
+            return self.error(info,
+                              &format!("{}: expected {} args, got {}",
+                                       name,
+                                       names.len(),
+                                       vals.len()));
+        }
+        self.alloc(info, names.len() as u64)?;
+        for (name, val) in names.iter().zip(vals) {
+            env.insert(*name, Rc::new(RefCell::new(val)));
+        }
+        Ok((env, code))
+    }
+
+    fn run_ap(&mut self, info: &Info) -> VMResult {
+        let f = match self.stack.pop().unwrap() {
+            Value::Ref(f) => f,
+            _ => return self.error(info, "AP: expected Closure"),
+        };
+        let args = self.stack.pop().unwrap();
+        match *f {
+            Lisp::Closure(..) => {
+                let (env, code) = self.enter(info, "AP", f, args)?;
+                let env = mem::replace(&mut self.env, env);
+                let code = mem::replace(&mut self.code, code);
+                self.dump.push(DumpOP::DumpAP(self.stack.len(), env, code));
+            }
+
+            Lisp::Native(ref native) => {
+                match args.as_lisp() {
+                    Some(Lisp::List(vals)) => self.run_native(info, native, vals)?,
+                    _ => return self.error(info, "AP: expected List"),
                 }
             }
-            _ => return self.error(&info, "AP: expected Closure"),
+            _ => return self.error(info, "AP: expected Closure"),
         }
         Ok(())
     }
 
     fn run_rap(&mut self, info: &Info) -> VMResult {
-        match *self.stack.pop().unwrap() {
-            Lisp::Closure(ref names, ref code, ref env) => {
-                match *self.stack.pop().unwrap() {
-                    Lisp::List(ref vals) => {
-                        let mut env = env.clone();
-                        for i in 0..names.len() {
-                            env.insert(names[i].clone(), vals[i].clone());
-                        }
-
-                        let stack = mem::replace(&mut self.stack, Vec::new());
-                        let code = mem::replace(&mut self.code, (code.clone(), 0));
-                        self.dump.push(DumpOP::DumpAP(stack, self.env.clone(), code));
-                        self.env.extend(env);
-                    }
+        let f = match self.stack.pop().unwrap() {
+            Value::Ref(f) => f,
+            _ => return self.error(info, "RAP: expected Closure"),
+        };
+        let args = self.stack.pop().unwrap();
+        match *f {
+            Lisp::Closure(..) => {
+                let (env, code) = self.enter(info, "RAP", f, args)?;
+                let code = mem::replace(&mut self.code, code);
+                self.dump.push(DumpOP::DumpAP(self.stack.len(), self.env.clone(), code));
+                self.env.extend(env);
+            }
 
-                    _ => return self.error(&info, "RAP: expected List"),
+            Lisp::Native(ref native) => {
+                match args.as_lisp() {
+                    Some(Lisp::List(vals)) => self.run_native(info, native, vals)?,
+                    _ => return self.error(info, "RAP: expected List"),
                 }
             }
 
-            _ => return self.error(&info, "RAP: expected Closure"),
+            _ => return self.error(info, "RAP: expected Closure"),
         }
         Ok(())
     }
 
-    fn run_ret(&mut self, info: &Info) -> VMResult {
-        let val = self.stack.pop().unwrap();
-        match self.dump.pop().unwrap() {
-            DumpOP::DumpAP(stack, env, code) => {
-                self.stack = stack;
-                self.env = env;
-                self.code = code;
+    fn run_native(&mut self, info: &Info, native: &Native, vals: &[Value]) -> VMResult {
+        if vals.len() != native.arity {
+            return self.error(info,
+                              &format!("{}: expected {} args, got {}",
+                                       native.name,
+                                       native.arity,
+                                       vals.len()));
+        }
 
+        match (native.fun)(self, vals) {
+            Ok(val) => {
                 self.stack.push(val);
-
                 Ok(())
             }
 
-            _ => self.error(&info, "RET: expected DumpAP"),
+            // a limit hit by a call back into Lisp stays a limit
+            Err(e) if e.is::<LimitExceeded>() => Err(e),
+            Err(e) => self.error(info, &format!("{}: {}", native.name, e)),
         }
     }
 
-    fn run_args(&mut self, _: &Info, n: usize) -> VMResult {
+    fn run_ret(&mut self, info: &Info) -> VMResult {
+        let val = self.stack.pop().unwrap();
+        let DumpOP::DumpAP(base, env, code) = match self.dump.pop() {
+            Some(d) => d,
+            None => return self.error(info, "RET: empty dump"),
+        };
+        self.stack.truncate(base);
+        self.env = env;
+        self.code = code;
+        self.stack.push(val);
+        Ok(())
+    }
+
+    fn run_args(&mut self, info: &Info, n: usize) -> VMResult {
+        self.alloc(info, n as u64 + 1)?;
         let slen = self.stack.len();
         let args = self.stack.split_off(slen - n);
-        self.stack.push(Rc::new(Lisp::List(args)));
+        self.stack.push(Value::new(Lisp::List(args)));
         Ok(())
     }
 
     fn run_puts(&mut self, _: &Info) -> VMResult {
-        println!("{}", *self.stack.last().unwrap());
+        let line = printer::display(self.stack.last().unwrap());
+        self.output.write_line(&line)?;
         Ok(())
     }
 
-    fn run_sel(&mut self, info: &Info, t: &Code, f: &Code) -> VMResult {
-        let b = self.stack.pop().unwrap();
-        let code = match *b {
-            Lisp::True => t,
-            Lisp::False => f,
-            _ => return self.error(&info, "SEL: expected bool"),
+    fn run_write(&mut self, _: &Info) -> VMResult {
+        let line = printer::write(self.stack.last().unwrap());
+        self.output.write_line(&line)?;
+        Ok(())
+    }
+
+    fn run_read(&mut self, info: &Info, n: usize) -> VMResult {
+        let ast = if n == 0 {
+            self.read_stdin(info)?
+        } else {
+            let a = self.stack.pop().unwrap();
+            let src = match chars_to_string(&a) {
+                Some(src) => src,
+                None => return self.error(info, "READ: expected list or vector of chars"),
+            };
+            match Parser::new(&src).read() {
+                Ok(Some(ast)) => Some(ast),
+                Ok(None) => return self.error(info, "READ: unexpected end of input"),
+                Err(e) => return self.error(info, &format!("READ: {}", e)),
+            }
+        };
+
+        let datum = match ast {
+            Some(ast) => {
+                self.alloc(info, allocations(&ast))?;
+                ast_to_lisp(&ast)
+            }
+            None => Value::Nil,
+        };
+        self.stack.push(datum);
+        Ok(())
+    }
+
+    // buffers stdin line by line until a whole datum is available;
+    // yields None at end of input
+    fn read_stdin(&mut self, info: &Info) -> Result<Option<AST>, Box<dyn Error>> {
+        loop {
+            let mut p = Parser::new(&self.input);
+            match p.read() {
+                Ok(Some(ast)) => {
+                    self.input = self.input[p.pos()..].to_string();
+                    return Ok(Some(ast));
+                }
+
+                Ok(None) => {}
+
+                Err(e) => {
+                    if p.unclosed() == 0 {
+                        self.input.clear();
+                        self.error(info, &format!("READ: {}", e))?;
+                    }
+                }
+            }
+
+            let mut line = String::new();
+            if io::stdin().read_line(&mut line)? == 0 {
+                if self.input.trim().is_empty() {
+                    self.input.clear();
+                    return Ok(None);
+                }
+
+                self.input.clear();
+                self.error(info, "READ: unexpected end of input")?;
+            }
+            self.input.push_str(&line);
+        }
+    }
+
+    fn run_eval(&mut self, info: &Info) -> VMResult {
+        let datum = self.stack.pop().unwrap();
+        let ast = match lisp_to_ast(&datum, *info) {
+            Ok(ast) => ast,
+            Err(e) => return self.error(info, &format!("EVAL: {}", e)),
         };
 
-        let code = mem::replace(&mut self.code, (code.clone(), 0));
-        self.dump.push(DumpOP::DumpSEL(code));
+        // the datum runs without local bindings, so it can only see globals
+        let mut compiler = Compiler::new();
+        compiler.set_globals(self.globals.keys().cloned());
+        compiler.compile_(ast)?;
+        compiler.code
+            .push(CodeOPInfo {
+                      info: *info,
+                      op: CodeOP::RET,
+                  });
+        self.alloc(info, compiler.code.len() as u64)?;
+
+        let env = mem::take(&mut self.env);
+        let code = mem::replace(&mut self.code,
+                                (Rc::new(compiler.code.into_boxed_slice()), 0));
+        self.dump.push(DumpOP::DumpAP(self.stack.len(), env, code));
 
         Ok(())
     }
 
-    fn run_join(&mut self, info: &Info) -> VMResult {
-        if let DumpOP::DumpSEL(code) = self.dump.pop().unwrap() {
-            self.code = code;
-            Ok(())
-        } else {
-            self.error(&info, "JOIN: expected DumpSEL")
+    fn run_jmp(&mut self, _: &Info, n: usize) -> VMResult {
+        self.code.1 += n;
+        Ok(())
+    }
+
+    fn run_jmpf(&mut self, info: &Info, n: usize) -> VMResult {
+        match self.stack.pop().unwrap() {
+            Value::True => {}
+            Value::False => self.code.1 += n,
+            _ => return self.error(info, "JMPF: expected bool"),
         }
+        Ok(())
     }
 
     fn run_eq(&mut self, _: &Info) -> VMResult {
         let a = self.stack.pop().unwrap();
         let b = self.stack.pop().unwrap();
         self.stack
-            .push(Rc::new(if a == b { Lisp::True } else { Lisp::False }));
+            .push(Value::bool(a == b));
         Ok(())
     }
 
     fn run_add(&mut self, info: &Info) -> VMResult {
         let a = self.stack.pop().unwrap();
-        if let Lisp::Int(n) = *a {
+        if let Value::Int(n) = a {
             let b = self.stack.pop().unwrap();
-            if let Lisp::Int(m) = *b {
-                self.stack.push(Rc::new(Lisp::Int(m + n)));
+            if let Value::Int(m) = b {
+                self.stack.push(Value::Int(m + n));
                 Ok(())
             } else {
-                self.error(&info, "ADD: expected int")
+                self.error(info, "ADD: expected int")
             }
         } else {
-            self.error(&info, "ADD: expected int")
+            self.error(info, "ADD: expected int")
         }
     }
 
     fn run_sub(&mut self, info: &Info) -> VMResult {
         let a = self.stack.pop().unwrap();
-        if let Lisp::Int(n) = *a {
+        if let Value::Int(n) = a {
             let b = self.stack.pop().unwrap();
-            if let Lisp::Int(o) = *b {
-                self.stack.push(Rc::new(Lisp::Int(o - n)));
+            if let Value::Int(o) = b {
+                self.stack.push(Value::Int(o - n));
                 Ok(())
             } else {
-                self.error(&info, "SUB: expected int")
+                self.error(info, "SUB: expected int")
             }
         } else {
-            self.error(&info, "SUB: expected int")
+            self.error(info, "SUB: expected int")
         }
     }
 
-    fn run_cons(&mut self, _: &Info) -> VMResult {
+    fn run_cons(&mut self, info: &Info) -> VMResult {
+        self.alloc(info, 1)?;
         let a = self.stack.pop().unwrap();
         let b = self.stack.pop().unwrap();
-        self.stack.push(Rc::new(Lisp::Cons(b, a)));
+        self.stack.push(Value::cons(b, a));
         Ok(())
     }
 
     fn run_car(&mut self, info: &Info) -> VMResult {
         let a = self.stack.pop().unwrap();
-        if let Lisp::Cons(ref car, _) = *a {
-            self.stack.push(car.clone());
+        if let Some(Lisp::Cons(car, _)) = a.as_lisp() {
+            self.stack.push(car.borrow().clone());
             Ok(())
         } else {
-            self.error(&info, "CAR: expected Cons")
+            self.error(info, "CAR: expected Cons")
         }
     }
 
     fn run_cdr(&mut self, info: &Info) -> VMResult {
         let a = self.stack.pop().unwrap();
-        if let Lisp::Cons(_, ref cdr) = *a {
-            self.stack.push(cdr.clone());
+        if let Some(Lisp::Cons(_, cdr)) = a.as_lisp() {
+            self.stack.push(cdr.borrow().clone());
+            Ok(())
+        } else {
+            self.error(info, "CDR: expected Cons")
+        }
+    }
+
+    fn run_setcar(&mut self, info: &Info) -> VMResult {
+        let val = self.stack.pop().unwrap();
+        let a = self.stack.pop().unwrap();
+        if let Some(Lisp::Cons(car, _)) = a.as_lisp() {
+            *car.borrow_mut() = val;
+            self.stack.push(Value::Nil);
+            Ok(())
+        } else {
+            self.error(info, "SETCAR: expected Cons")
+        }
+    }
+
+    fn run_setcdr(&mut self, info: &Info) -> VMResult {
+        let val = self.stack.pop().unwrap();
+        let a = self.stack.pop().unwrap();
+        if let Some(Lisp::Cons(_, cdr)) = a.as_lisp() {
+            *cdr.borrow_mut() = val;
+            self.stack.push(Value::Nil);
+            Ok(())
+        } else {
+            self.error(info, "SETCDR: expected Cons")
+        }
+    }
+
+    fn run_box(&mut self, info: &Info) -> VMResult {
+        self.alloc(info, 1)?;
+        let a = self.stack.pop().unwrap();
+        self.stack.push(Value::new(Lisp::Box(RefCell::new(a))));
+        Ok(())
+    }
+
+    fn run_unbox(&mut self, info: &Info) -> VMResult {
+        let a = self.stack.pop().unwrap();
+        if let Some(Lisp::Box(val)) = a.as_lisp() {
+            self.stack.push(val.borrow().clone());
+            Ok(())
+        } else {
+            self.error(info, "UNBOX: expected Box")
+        }
+    }
+
+    fn run_setbox(&mut self, info: &Info) -> VMResult {
+        let val = self.stack.pop().unwrap();
+        let a = self.stack.pop().unwrap();
+        if let Some(Lisp::Box(cell)) = a.as_lisp() {
+            *cell.borrow_mut() = val;
+            self.stack.push(Value::Nil);
+            Ok(())
+        } else {
+            self.error(info, "SETBOX: expected Box")
+        }
+    }
+
+    fn run_vector(&mut self, info: &Info, n: usize) -> VMResult {
+        self.alloc(info, n as u64 + 1)?;
+        let slen = self.stack.len();
+        let vals = self.stack.split_off(slen - n);
+        self.stack.push(Value::new(Lisp::Vector(RefCell::new(vals))));
+        Ok(())
+    }
+
+    fn run_mkvector(&mut self, info: &Info) -> VMResult {
+        let fill = self.stack.pop().unwrap();
+        let a = self.stack.pop().unwrap();
+        match a {
+            Value::Int(n) if n >= 0 => {
+                self.alloc(info, n as u64 + 1)?;
+                let vals = vec![fill; n as usize];
+                self.stack.push(Value::new(Lisp::Vector(RefCell::new(vals))));
+                Ok(())
+            }
+
+            _ => self.error(info, "MKVECTOR: expected non-negative int"),
+        }
+    }
+
+    fn run_vectorref(&mut self, info: &Info) -> VMResult {
+        let i = self.stack.pop().unwrap();
+        let a = self.stack.pop().unwrap();
+        match (a.as_lisp(), &i) {
+            (Some(Lisp::Vector(vals)), Value::Int(i)) => {
+                let val = match vals.borrow().get(*i as usize) {
+                    Some(val) if *i >= 0 => val.clone(),
+                    _ => return self.error(info, "VECTORREF: index out of range"),
+                };
+                self.stack.push(val);
+                Ok(())
+            }
+
+            _ => self.error(info, "VECTORREF: expected Vector and int"),
+        }
+    }
+
+    fn run_vectorset(&mut self, info: &Info) -> VMResult {
+        let val = self.stack.pop().unwrap();
+        let i = self.stack.pop().unwrap();
+        let a = self.stack.pop().unwrap();
+        match (a.as_lisp(), &i) {
+            (Some(Lisp::Vector(vals)), Value::Int(i)) => {
+                match vals.borrow_mut().get_mut(*i as usize) {
+                    Some(slot) if *i >= 0 => *slot = val,
+                    _ => return self.error(info, "VECTORSET: index out of range"),
+                }
+                self.stack.push(Value::Nil);
+                Ok(())
+            }
+
+            _ => self.error(info, "VECTORSET: expected Vector and int"),
+        }
+    }
+
+    fn run_vectorlen(&mut self, info: &Info) -> VMResult {
+        let a = self.stack.pop().unwrap();
+        if let Some(Lisp::Vector(vals)) = a.as_lisp() {
+            let n = vals.borrow().len() as i32;
+            self.stack.push(Value::Int(n));
+            Ok(())
+        } else {
+            self.error(info, "VECTORLEN: expected Vector")
+        }
+    }
+
+    fn run_mkhash(&mut self, info: &Info) -> VMResult {
+        self.alloc(info, 1)?;
+        self.stack.push(Value::new(Lisp::HashTable(RefCell::new(HashMap::new()))));
+        Ok(())
+    }
+
+    fn run_hashref(&mut self, info: &Info) -> VMResult {
+        let key = self.stack.pop().unwrap();
+        let a = self.stack.pop().unwrap();
+        if let Some(Lisp::HashTable(table)) = a.as_lisp() {
+            let val = match table.borrow().get(&key) {
+                Some(val) => val.clone(),
+                None => return self.error(info, &format!("HASHREF: no value for key {}", key)),
+            };
+            self.stack.push(val);
             Ok(())
         } else {
-            self.error(&info, "CDR: expected Cons")
+            self.error(info, "HASHREF: expected HashTable")
         }
     }
+
+    fn run_hashset(&mut self, info: &Info) -> VMResult {
+        let val = self.stack.pop().unwrap();
+        let key = self.stack.pop().unwrap();
+        let a = self.stack.pop().unwrap();
+        if let Some(Lisp::HashTable(table)) = a.as_lisp() {
+            self.alloc(info, 1)?;
+            table.borrow_mut().insert(key, val);
+            self.stack.push(Value::Nil);
+            Ok(())
+        } else {
+            self.error(info, "HASHSET: expected HashTable")
+        }
+    }
+
+    fn run_hashhas(&mut self, info: &Info) -> VMResult {
+        let key = self.stack.pop().unwrap();
+        let a = self.stack.pop().unwrap();
+        if let Some(Lisp::HashTable(table)) = a.as_lisp() {
+            let has = table.borrow().contains_key(&key);
+            self.stack.push(Value::bool(has));
+            Ok(())
+        } else {
+            self.error(info, "HASHHAS: expected HashTable")
+        }
+    }
+
+    fn run_hashremove(&mut self, info: &Info) -> VMResult {
+        let key = self.stack.pop().unwrap();
+        let a = self.stack.pop().unwrap();
+        if let Some(Lisp::HashTable(table)) = a.as_lisp() {
+            table.borrow_mut().remove(&key);
+            self.stack.push(Value::Nil);
+            Ok(())
+        } else {
+            self.error(info, "HASHREMOVE: expected HashTable")
+        }
+    }
+
+    fn run_hashkeys(&mut self, info: &Info) -> VMResult {
+        let a = self.stack.pop().unwrap();
+        if let Some(Lisp::HashTable(table)) = a.as_lisp() {
+            let keys: Vec<Value> = table.borrow().keys().cloned().collect();
+            self.alloc(info, keys.len() as u64)?;
+            self.stack.push(Value::from_vec(keys));
+            Ok(())
+        } else {
+            self.error(info, "HASHKEYS: expected HashTable")
+        }
+    }
+
+    fn run_hashcount(&mut self, info: &Info) -> VMResult {
+        let a = self.stack.pop().unwrap();
+        if let Some(Lisp::HashTable(table)) = a.as_lisp() {
+            let n = table.borrow().len() as i32;
+            self.stack.push(Value::Int(n));
+            Ok(())
+        } else {
+            self.error(info, "HASHCOUNT: expected HashTable")
+        }
+    }
+
+    fn run_char2int(&mut self, info: &Info) -> VMResult {
+        let a = self.stack.pop().unwrap();
+        if let Value::Char(c) = a {
+            self.stack.push(Value::Int(c as i32));
+            Ok(())
+        } else {
+            self.error(info, "CHAR2INT: expected char")
+        }
+    }
+
+    fn run_int2char(&mut self, info: &Info) -> VMResult {
+        let a = self.stack.pop().unwrap();
+        if let Value::Int(n) = a {
+            match ::std::char::from_u32(n as u32) {
+                Some(c) if n >= 0 => {
+                    self.stack.push(Value::Char(c));
+                    Ok(())
+                }
+
+                _ => self.error(info, "INT2CHAR: invalid code point"),
+            }
+        } else {
+            self.error(info, "INT2CHAR: expected int")
+        }
+    }
+
+    fn run_chareq(&mut self, info: &Info) -> VMResult {
+        let a = self.stack.pop().unwrap();
+        let b = self.stack.pop().unwrap();
+        if let (Value::Char(c), Value::Char(d)) = (&b, &a) {
+            self.stack.push(Value::bool(c == d));
+            Ok(())
+        } else {
+            self.error(info, "CHAREQ: expected char")
+        }
+    }
+
+    fn run_charlt(&mut self, info: &Info) -> VMResult {
+        let a = self.stack.pop().unwrap();
+        let b = self.stack.pop().unwrap();
+        if let (Value::Char(c), Value::Char(d)) = (&b, &a) {
+            self.stack.push(Value::bool(c < d));
+            Ok(())
+        } else {
+            self.error(info, "CHARLT: expected char")
+        }
+    }
+}
+
+fn chars_to_string(val: &Value) -> Option<String> {
+    let vals = match val.as_lisp() {
+        Some(Lisp::Vector(vals)) => vals.borrow().clone(),
+        _ => val.list_to_vec()?,
+    };
+
+    vals.iter()
+        .map(|val| match *val {
+                 Value::Char(c) => Some(c),
+                 _ => None,
+             })
+        .collect()
 }
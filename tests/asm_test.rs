@@ -0,0 +1,73 @@
+extern crate secd;
+use secd::*;
+
+#[test]
+fn inline_closure() {
+  let code = assemble(r#"
+    ; (+ 1 ((lambda (x) (if (eq x 0) 0 x)) 41))
+    LDC 1
+    LDC 41
+    ARGS 1
+    CLOSURE (x) {
+      LD x LDC 0 EQ
+      JMPF else
+      LDC 0
+      JMP end
+    else:
+      LD x
+    end:
+      RET
+    }
+    AP
+    ADD
+  "#).unwrap();
+
+  assert_eq!(code[0].info, [3, 5]);
+  let val = Interpreter::new().eval_code(code).unwrap();
+  assert_eq!(val, Value::Int(42));
+}
+
+#[test]
+fn round_trip() {
+  for src in &["(let f (lambda (x) (if (eq x #\\space) nil (cons x 1))) (f #\\())",
+               "(letrec f (lambda n (if (eq n 0) true (f (- n 1)))) (f 10))",
+               "(let v (vector 1 2) (let _ (vector-set! v 0 (- 0 3)) (read)))"] {
+    let code = compile_lisp(src).unwrap();
+    let listing = disassemble(&code);
+    let code2 = assemble(&listing).unwrap();
+    assert_eq!(code2, code);
+    assert_eq!(disassemble(&code2), listing);
+  }
+}
+
+#[test]
+fn labels() {
+  let code = assemble("LDC true JMPF L1\nLDC 1 JMP 0\nL1: LDC 2").unwrap();
+  assert_eq!(disassemble(&code), "     0  1:1     LDC true
+     1  1:10    JMPF 2  ; -> 4
+     2  2:1     LDC 1
+     3  2:7     JMP 0  ; -> 4
+     4  3:5     LDC 2
+");
+}
+
+#[test]
+fn errors() {
+  let err = |s: &str| format!("{}", assemble(s).unwrap_err());
+  assert_eq!(err("LDC 1\n  PUSH"), "2:3:asm error: unknown instruction 'PUSH'");
+  assert_eq!(err("JMPF L1"), "1:6:asm error: undefined label L1");
+  assert_eq!(err("CLOSURE (x) {\n LD x"), "1:13:asm error: unclosed '{'");
+  assert_eq!(err("LDC foo"), "1:5:asm error: bad constant 'foo'");
+  assert_eq!(err("ARGS"), "1:1:asm error: expected a number, got end of input");
+  assert_eq!(err("L1: LDC 1 JMP L1"), "1:15:asm error: label L1 is not after the jump");
+  assert_eq!(err("L1: RET\nL1: RET"), "2:1:asm error: duplicate label L1");
+  assert_eq!(err("RET }"), "1:5:asm error: unexpected '}'");
+
+  // code that would crash the machine
+  assert_eq!(err("LDC 1\nJMP 100"), "2:1:asm error: jump of 100 runs past the end of the code");
+  assert_eq!(err("ADD"), "1:1:asm error: ADD needs 2 values on the stack, got 0");
+  assert_eq!(err("CLOSURE () {\n  LDC 1 JMP out RET\n}\nout: LDC 2"),
+             "2:9:asm error: jump of 1 runs past the end of its closure body");
+  assert_eq!(err("CLOSURE () 3 RET"),
+             "1:1:asm error: closure body of 3 instructions runs past the end of the code");
+}
@@ -0,0 +1,69 @@
+extern crate secd;
+use secd::*;
+use secd::data::Code;
+
+const SRC: &str = r#"
+  (define twice (lambda f (lambda x (f (f x)))))
+  (letrec f (lambda n (if (eq n 0) #\a (f (- n 1))))
+    (cons (f 3) ((twice (lambda x (+ x 1))) 40)))
+"#;
+
+#[test]
+fn round_trip() {
+  let code = compile_lisp(SRC).unwrap();
+  let bytes = bytecode::encode(&code);
+  assert!(bytecode::is_bytecode(&bytes));
+
+  let loaded = bytecode::decode(&bytes).unwrap();
+  assert_eq!(loaded, code);
+  let infos = |c: &Code| c.iter().map(|op| op.info).collect::<Vec<_>>();
+  assert_eq!(infos(&loaded), infos(&code));
+  assert_eq!(bytecode::encode(&loaded), bytes);
+
+  let val = Interpreter::new().eval_code(loaded).unwrap();
+  assert_eq!(val, run_lisp(SRC).unwrap());
+  assert_eq!(format!("{}", val), "(a . 42)");
+}
+
+#[test]
+fn rejects_bad_files() {
+  let bytes = bytecode::encode(&compile_lisp(SRC).unwrap());
+  let err = |b: &[u8]| format!("{}", bytecode::decode(b).unwrap_err());
+
+  assert_eq!(err(b"(+ 1 2)"), "bytecode error: bad magic number");
+
+  let mut old = bytes.clone();
+  old[8] = 0;
+  assert_eq!(err(&old), "bytecode error: unsupported version 0 (expected 3)");
+
+  assert_eq!(err(&bytes[..12]), "bytecode error: unexpected end of data");
+
+  for i in 12..bytes.len() {
+    let mut bad = bytes.clone();
+    bad[i] ^= 0x10;
+    assert_eq!(err(&bad), "bytecode error: checksum mismatch, file is corrupt");
+  }
+}
+
+#[test]
+fn rejects_bad_code() {
+  let code = |ops: Vec<data::CodeOP>| -> Code {
+    std::rc::Rc::new(ops.into_iter().map(|op| data::CodeOPInfo { info: [1, 1], op }).collect())
+  };
+  let err = |c: Code| format!("{}", bytecode::decode(&bytecode::encode(&c)).unwrap_err());
+
+  use secd::data::CodeOP::*;
+  assert_eq!(err(code(vec![LDC(Value::True), JMPF(100), LDC(Value::Nil)])),
+             "bytecode error: instruction 1: jump of 100 runs past the end of the code");
+  assert_eq!(err(code(vec![CLOSURE(std::rc::Rc::new(Box::new([])), 5), RET])),
+             "bytecode error: instruction 0: closure body of 5 instructions runs past the end of the code");
+  assert_eq!(err(code(vec![LDC(Value::Nil), ADD])),
+             "bytecode error: instruction 1: ADD needs 2 values on the stack, got 1");
+}
+
+#[test]
+fn empty_program() {
+  assert!(compile_lisp("").is_err());
+  assert_eq!(Interpreter::new().eval_code(compile_lisp("1 2").unwrap()).unwrap(),
+             Value::Int(2));
+}
@@ -6,22 +6,22 @@ use std::rc::Rc;
 
 #[test]
 fn compile() {
-    let code1 = Compiler::new().compile(&Parser::new(&"(let a 0 (letrec b (a 0) (puts b)))"
+    let code1 = Compiler::new().compile(Parser::new(&"(let a 0 (letrec b (a 0) (puts b)))"
                                                           .into())
                                                  .parse()
                                                  .unwrap());
 
     let code2 = vec![CodeOPInfo {
                          info: [0; 2],
-                         op: CodeOP::LDC(Rc::new(Lisp::Int(0))),
+                         op: CodeOP::LDC(Value::Int(0)),
                      },
                      CodeOPInfo {
                          info: [0; 2],
-                         op: CodeOP::LET("a".into()),
+                         op: CodeOP::LET("a'1".into()),
                      },
                      CodeOPInfo {
                          info: [0; 2],
-                         op: CodeOP::LDC(Rc::new(Lisp::Int(0))),
+                         op: CodeOP::LDC(Value::Int(0)),
                      },
                      CodeOPInfo {
                          info: [0; 2],
@@ -29,7 +29,7 @@ fn compile() {
                      },
                      CodeOPInfo {
                          info: [0; 2],
-                         op: CodeOP::LD("a".into()),
+                         op: CodeOP::LD("a'1".into()),
                      },
                      CodeOPInfo {
                          info: [0; 2],
@@ -37,11 +37,11 @@ fn compile() {
                      },
                      CodeOPInfo {
                          info: [0; 2],
-                         op: CodeOP::LET("b".into()),
+                         op: CodeOP::LET("b'2".into()),
                      },
                      CodeOPInfo {
                          info: [0; 2],
-                         op: CodeOP::LD("b".into()),
+                         op: CodeOP::LD("b'2".into()),
                      },
                      CodeOPInfo {
                          info: [0; 2],
@@ -49,5 +49,98 @@ fn compile() {
                      }];
 
     assert!(code1.is_ok());
-    assert_eq!(code1.unwrap(), code2);
+    assert_eq!(code1.unwrap(), Rc::new(code2.into_boxed_slice()));
+}
+
+#[test]
+fn compile_asm() {
+    let code = Compiler::new().compile(Parser::new(&"(let a 0 (if (eq a 0) (puts a) nil))".into())
+                                           .parse()
+                                           .unwrap());
+
+    assert_eq!(code.unwrap(),
+               assemble("LDC 0 LET a'1
+                         LD a'1 LDC 0 EQ
+                         JMPF else
+                         LD a'1 PUTS
+                         JMP end
+                         else: LDC nil
+                         end:")
+                   .unwrap());
+}
+
+#[test]
+fn binder_names() {
+    // a let runs in the frame of its enclosing body, so each binder needs a
+    // runtime name of its own for the outer x to survive the inner one
+    for &(src, val) in &[("(let x 1 (cons (let x 2 x) x))", "(2 . 1)"),
+                         ("(define x 1) (cons (let x 2 x) x)", "(2 . 1)"),
+                         ("((lambda x (cons (let x 2 x) x)) 1)", "(2 . 1)"),
+                         ("(define y 1) (letrec f (lambda n y) ((lambda y (f 0)) 2))", "1")] {
+        assert_eq!(format!("{}", run_lisp(src).unwrap()), val, "{}", src);
+    }
+    assert!(!disassemble(&compile_lisp("(let x 1 (cons (let x 2 x) x))").unwrap()).contains("AP"));
+
+    assert_eq!(format!("{}", run_lisp("(lambda (a b) a)").unwrap()), "#<closure (a b)>");
+}
+
+#[test]
+fn beta_reduce() {
+    let code = compile_lisp("((lambda (x y) (cons y x)) 1 2)").unwrap();
+    assert_eq!(code,
+               assemble("LDC 1 LET x'1 LDC 2 LET y'2
+                         LD y'2 LD x'1 CONS")
+                   .unwrap());
+
+    assert_eq!(format!("{}", run_lisp("((lambda (x y) (cons y x)) 1 2)").unwrap()),
+               "(2 . 1)");
+
+    // each form numbers its fresh names from 1 again; a closure keeps the
+    // binding it captured when a later form reuses the name
+    let src = "(define g ((lambda x (lambda _ x)) 1)) ((lambda x x) 2) (g nil)";
+    assert_eq!(disassemble(&compile_lisp(src).unwrap()).matches("LET x'1").count(), 2);
+    assert_eq!(run_lisp(src).unwrap(), Value::Int(1));
+}
+
+#[test]
+fn inline_let_bound() {
+    let src = "(let inc (lambda n (+ n 1)) (inc (inc 1)))";
+    assert!(!disassemble(&compile_lisp(src).unwrap()).contains("AP"));
+    assert_eq!(run_lisp(src).unwrap(), Value::Int(3));
+
+    // recursive, evaluating or wrongly applied functions stay calls
+    for src in &["(letrec f (lambda n (if (eq n 0) 0 (f (- n 1)))) (f 3))",
+                 "(let f (lambda x (eval x)) (f 1))",
+                 "(let f (lambda x x) (f 1 2))",
+                 "((lambda x x))"] {
+        assert!(disassemble(&compile_lisp(src).unwrap()).contains("AP"), "{}", src);
+    }
+    assert!(run_lisp("(let f (lambda x x) (f 1 2))")
+                .unwrap_err()
+                .to_string()
+                .contains("expected 1 args, got 2"));
+}
+
+#[test]
+fn inline_capture() {
+    for &(src, expected) in &[// arguments are bound together, not one after another
+                              ("(let a 1 (let b 2 ((lambda (a b) (cons a b)) b a)))", "(2 . 1)"),
+                              // a free argument is not captured by a binder in the body
+                              ("(let y 10 (((lambda x (lambda y (+ x y))) y) 1))", "11"),
+                              // bindings in an inlined body do not outlive it
+                              ("(let x 5 (cons ((lambda x (let y x y)) 1) (cons x y)))",
+                               "unbound variable 'y'"),
+                              ("(let x 5 (cons ((lambda y (let x y x)) 1) x))", "(1 . 5)"),
+                              // a free variable of an inlined function keeps its binding
+                              ("(let y 1 (let f (lambda x (+ x y)) (let y 2 (f 0))))", "1"),
+                              ("(let y 1 (let f (lambda x (+ x y)) (cons (f 0) ((lambda y (f y)) 5))))",
+                               "(1 . 6)"),
+                              ("(let f (lambda x (let _ (set! x (+ x 1)) x)) (let g (lambda x (cons (f x) x)) (g 1)))",
+                               "(2 . 1)")] {
+        let r = match run_lisp(src) {
+            Ok(val) => format!("{}", val),
+            Err(e) => e.to_string(),
+        };
+        assert!(r.contains(expected), "{} => {}", src, r);
+    }
 }
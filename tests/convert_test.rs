@@ -0,0 +1,74 @@
+extern crate secd;
+use secd::*;
+use std::collections::HashMap;
+
+#[derive(Debug, PartialEq)]
+struct Config {
+  name: String,
+  size: u16,
+  tags: Vec<(char, bool)>,
+}
+lisp_struct!(Config { name, size, tags });
+
+#[test]
+fn primitives() {
+  assert_eq!(42i32.to_lisp(), Value::Int(42));
+  assert_eq!(i32::from_lisp(&42i32.to_lisp()).unwrap(), 42);
+  assert!(u8::from_lisp(&300i32.to_lisp()).is_err());
+  assert!(bool::from_lisp(&true.to_lisp()).unwrap());
+  assert_eq!(<()>::from_lisp(&().to_lisp()).unwrap(), ());
+  assert_eq!(String::from_lisp(&"abc".to_lisp()).unwrap(), "abc");
+  assert!(i32::from_lisp(&Value::Nil).is_err());
+}
+
+#[test]
+fn containers() {
+  let v = vec![Some(1i32), None, Some(3)];
+  assert_eq!(format!("{}", v.to_lisp()), "(1 nil 3)");
+  assert_eq!(Vec::<Option<i32>>::from_lisp(&v.to_lisp()).unwrap(), v);
+
+  let t = (1i32, 'a', false);
+  assert_eq!(<(i32, char, bool)>::from_lisp(&t.to_lisp()).unwrap(), t);
+
+  let mut m = HashMap::new();
+  m.insert(1i32, "one".to_string());
+  m.insert(2i32, "two".to_string());
+  assert_eq!(HashMap::<i32, String>::from_lisp(&m.to_lisp()).unwrap(), m);
+}
+
+#[test]
+fn options_of_nil() {
+  // Some of a value that is nil in Lisp can not be told from None
+  assert_eq!(Some(()).to_lisp(), Value::Nil);
+  assert_eq!(Option::<()>::from_lisp(&Some(()).to_lisp()).unwrap(), None);
+  assert_eq!(Option::<String>::from_lisp(&Some(String::new()).to_lisp()).unwrap(), None);
+  assert_eq!(Option::<Vec<i32>>::from_lisp(&Some(Vec::<i32>::new()).to_lisp()).unwrap(), None);
+  assert_eq!(Option::<String>::from_lisp(&Some("a".to_string()).to_lisp()).unwrap(), Some("a".to_string()));
+}
+
+#[test]
+fn cyclic_list() {
+  let mut interp = Interpreter::new();
+  let l = interp.eval_str("(let l (cons 1 (cons 2 nil)) (let _ (set-cdr! (cdr l) l) l))").unwrap();
+  assert_eq!(Vec::<i32>::from_lisp(&l).unwrap_err().to_string(), "expected list, got #0=(1 2 . #0#)");
+  assert!(<(i32, i32)>::from_lisp(&l).is_err());
+}
+
+#[test]
+fn struct_() {
+  let c = Config { name: "x".into(), size: 3, tags: vec![('a', true)] };
+  assert_eq!(Config::from_lisp(&c.to_lisp()).unwrap(), c);
+  assert!(Config::from_lisp(&Value::Nil).is_err());
+}
+
+#[test]
+fn native() {
+  let mut interp = Interpreter::new();
+  interp.register_fn("sum", 1, |args| {
+    let ns = Vec::<i32>::from_lisp(&args[0])?;
+    Ok(ns.iter().sum::<i32>().to_lisp())
+  });
+
+  let r = interp.eval_str("(sum (cons 1 (cons 2 (cons 3 nil))))").unwrap();
+  assert_eq!(i32::from_lisp(&r).unwrap(), 6);
+}
@@ -0,0 +1,38 @@
+extern crate secd;
+use secd::*;
+
+#[test]
+fn listing() {
+  let code = compile_lisp("(let f (lambda (x) (if (eq x #\\a) nil 1))\n  (f #\\b))").unwrap();
+  assert_eq!(disassemble(&code), "     0  1:15    CLOSURE (x'1) 8  ; -> 9
+     1  1:29      LD x'1
+     2  1:33      LDC #\\a
+     3  1:27      EQ
+     4  1:23      JMPF 2  ; -> 7
+     5  1:38      LDC nil
+     6  1:38      JMP 1  ; -> 8
+     7  1:40      LDC 1
+     8  1:15      RET
+     9  1:5     LET f'2
+    10  2:9     LDC #\\b
+    11  2:5     LET x'3
+    12  1:29    LD x'3
+    13  1:33    LDC #\\a
+    14  1:27    EQ
+    15  1:23    JMPF 2  ; -> 18
+    16  1:38    LDC nil
+    17  1:38    JMP 1  ; -> 19
+    18  1:40    LDC 1
+");
+}
+
+#[test]
+fn nested_closures() {
+  let code = compile_lisp("(lambda x (lambda y x))").unwrap();
+  assert_eq!(disassemble(&code), "     0  1:8     CLOSURE (x'1) 4  ; -> 5
+     1  1:18      CLOSURE (y'2) 2  ; -> 4
+     2  1:22        LD x'1
+     3  1:18        RET
+     4  1:8       RET
+");
+}
@@ -0,0 +1,108 @@
+extern crate secd;
+use secd::*;
+
+#[test]
+fn register_fn() {
+  let mut interp = Interpreter::new();
+  interp.register_fn("mul", 2, |args| {
+    match (&args[0], &args[1]) {
+      (&Value::Int(a), &Value::Int(b)) => Ok(Value::Int(a * b)),
+      _ => Err(From::from("expected int")),
+    }
+  });
+
+  let r = interp.eval_str("(let sq (lambda x (mul x x)) (sq (mul 2 3)))");
+  assert!(r.is_ok());
+  assert_eq!(r.unwrap(), Value::Int(36));
+
+  let r = interp.eval_str("(mul 1 nil)");
+  assert!(r.unwrap_err().to_string().contains("mul: expected int"));
+
+  let r = interp.eval_str("(mul 1)");
+  assert!(r.unwrap_err().to_string().contains("mul: expected 2 args, got 1"));
+}
+
+#[test]
+fn native_in_letrec() {
+  let mut interp = Interpreter::new();
+  interp.register_fn("zero?", 1, |args| {
+    Ok(Value::bool(args[0] == Value::Int(0)))
+  });
+
+  let r = interp.eval_str("(letrec f (lambda n (if (zero? n) 0 (+ 2 (f (- n 1))))) (f 5))");
+  assert!(r.is_ok());
+  assert_eq!(r.unwrap(), Value::Int(10));
+}
+
+#[test]
+fn session() {
+  let mut interp = Interpreter::new();
+  interp.define("base", 10i32);
+
+  let r = interp.eval_str("(define f (lambda n (if (eq n 0) base (+ 1 (f (- n 1))))))");
+  assert!(r.is_ok());
+  let r = interp.eval_str("(define x (f 3)) (set! base 0) (f 3)");
+  assert_eq!(r.unwrap(), Value::Int(3));
+
+  assert_eq!(i32::from_lisp(&interp.lookup("x").unwrap()).unwrap(), 13);
+  assert!(interp.remove("x").is_some());
+  assert!(interp.lookup("x").is_none());
+  assert!(interp.eval_str("x").unwrap_err().to_string().contains("unbound variable 'x'"));
+}
+
+#[test]
+fn define_visible_to_eval() {
+  let mut interp = Interpreter::new();
+  let r = interp.eval_str("(define y 5) (eval (read #(#\\y)))");
+  assert_eq!(r.unwrap(), Value::Int(5));
+}
+
+#[test]
+fn call_closure() {
+  let mut interp = Interpreter::new();
+  interp.eval_str("(define fib (lambda n (if (eq n 0) 0 (if (eq n 1) 1 (+ (fib (- n 1)) (fib (- n 2)))))))").unwrap();
+
+  let fib = interp.lookup("fib").unwrap();
+  let r = interp.call(&fib, &[10i32.to_lisp()]);
+  assert_eq!(r.unwrap(), Value::Int(55));
+
+  assert!(interp.call(&fib, &[]).is_err());
+  assert!(interp.call(&Value::Nil, &[]).is_err());
+}
+
+#[test]
+fn native_calls_back() {
+  let mut interp = Interpreter::new();
+  interp.register_fn("lt", 2, |args| {
+    Ok((i32::from_lisp(&args[0])? < i32::from_lisp(&args[1])?).to_lisp())
+  });
+  interp.register_fn_with_vm("sort", 2, |vm, args| {
+    let mut vals = Vec::<Value>::from_lisp(&args[0])?;
+    for i in 1..vals.len() {
+      let mut j = i;
+      while j > 0 && bool::from_lisp(&vm.call(&args[1], &[vals[j].clone(), vals[j - 1].clone()])?)? {
+        vals.swap(j, j - 1);
+        j -= 1;
+      }
+    }
+    Ok(vals.to_lisp())
+  });
+
+  let r = interp.eval_str(r#"
+    (let ls (sort (cons 3 (cons 1 (cons 2 nil))) (lambda (a b) (lt a b)))
+      (cons (car ls) (sort ls (lambda (a b) (lt b a)))))
+  "#);
+  assert_eq!(format!("{}", r.unwrap()), "(1 3 2 1)");
+}
+
+#[test]
+fn call_letrec_closure() {
+  // a letrec closure finds itself only when called from its scope
+  let mut interp = Interpreter::new();
+  let f = interp.eval_str("(letrec loop (lambda n (if (eq n 0) 0 (loop (- n 1)))) loop)").unwrap();
+  assert_eq!(interp.call(&f, &[3.to_lisp()]).unwrap_err().to_string(),
+             "vm error: call: closure refers to 'loop', which is bound outside it, such as by letrec");
+
+  let g = interp.eval_str("(lambda n (let m (+ n 1) ((lambda k (+ k m)) m)))").unwrap();
+  assert_eq!(interp.call(&g, &[3.to_lisp()]).unwrap(), Value::Int(8));
+}
@@ -0,0 +1,85 @@
+extern crate secd;
+use secd::*;
+use secd::ir::*;
+
+fn lower(s: &str) -> Expr {
+  let mut lowerer = Lowerer::new();
+  lowerer.declare("x".into());
+  lowerer.declare("f".into());
+  lowerer.lower(Parser::new(&s.into()).parse().unwrap()).unwrap()
+}
+
+#[test]
+fn desugar() {
+  let e = lower("(if (eq x 1) (puts #\\a) nil)");
+  assert_eq!(e.info, [1, 4]);
+  match e.kind {
+    ExprKind::If(cond, then, else_) => {
+      match cond.kind {
+        ExprKind::PrimOp(data::CodeOP::EQ, ref args) => {
+          assert_eq!(args[0].kind, ExprKind::Var(Var::Free("x".into())));
+          assert_eq!(args[1].kind, ExprKind::Const(Value::Int(1)));
+        }
+        ref k => panic!("{:?}", k),
+      }
+      assert_eq!(then.info, [1, 19]);
+      assert_eq!(else_.kind, ExprKind::Const(Value::Nil));
+    }
+    k => panic!("{:?}", k),
+  }
+
+  assert_eq!(lower("(f 1 #(2))").size(), 5);
+}
+
+#[test]
+fn scopes() {
+  // the inner x is the lambda's, the outer one the let's
+  let e = lower("(let x 1 (cons (lambda x x) x))");
+  let (x, body) = match e.kind {
+    ExprKind::Let(x, _, body) => (x, body),
+    k => panic!("{:?}", k),
+  };
+  let args = match body.kind {
+    ExprKind::PrimOp(_, args) => args,
+    k => panic!("{:?}", k),
+  };
+  match args[0].kind {
+    ExprKind::Lambda(ref params, ref body) => {
+      assert_eq!(body.kind, ExprKind::Var(Var::Bound(params[0])));
+      assert!(params[0] != x);
+    }
+    ref k => panic!("{:?}", k),
+  }
+  assert_eq!(args[1].kind, ExprKind::Var(Var::Bound(x)));
+
+  // a letrec is in scope in its own expression, a let is not
+  match lower("(letrec f (lambda n (f n)) f)").kind {
+    ExprKind::LetRec(f, expr, _) => {
+      assert!(f.rec);
+      let mut vars = Vec::new();
+      expr.walk(&mut |e| if let ExprKind::Var(v) = e.kind { vars.push(v) });
+      assert!(vars.contains(&Var::Bound(f)));
+    }
+    k => panic!("{:?}", k),
+  }
+  match lower("(let f (lambda n (f n)) f)").kind {
+    ExprKind::Let(f, expr, _) => {
+      let mut vars = Vec::new();
+      expr.walk(&mut |e| if let ExprKind::Var(v) = e.kind { vars.push(v) });
+      assert!(vars.contains(&Var::Free("f".into())));
+      assert!(!vars.contains(&Var::Bound(f)));
+    }
+    k => panic!("{:?}", k),
+  }
+}
+
+#[test]
+fn errors() {
+  for &(src, msg) in &[("(let x 1)", "1:5:compile error: let syntax"),
+                       ("(f (lambda 1 x))", "1:13:compile error: lambda args"),
+                       ("(1 2)", "1:3:compile error: apply unexpect int"),
+                       ("(car (+ 1))", "1:8:compile error: add syntax")] {
+    let r = Lowerer::new().lower(Parser::new(&src.into()).parse().unwrap());
+    assert_eq!(r.unwrap_err().to_string(), msg);
+  }
+}
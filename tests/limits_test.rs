@@ -0,0 +1,111 @@
+extern crate secd;
+use secd::*;
+
+fn machine(s: &str, limits: Limits) -> SECD {
+  let mut secd = SECD::new(
+    Compiler::new().compile(
+      Parser::new(&s.into()).parse().unwrap()
+    ).unwrap()
+  );
+  secd.limits = limits;
+  secd
+}
+
+fn exceeded(secd: &mut SECD) -> LimitExceeded {
+  let e = secd.run().unwrap_err();
+  e.downcast_ref::<LimitExceeded>().expect("LimitExceeded").clone()
+}
+
+#[test]
+fn instructions() {
+  let mut secd = machine("(letrec f (lambda n (f n)) (f 0))",
+                         Limits { instructions: Some(100), ..Limits::default() });
+  assert_eq!(exceeded(&mut secd).limit, Limit::Instructions);
+  assert_eq!(secd.executed, 100);
+  assert!(!secd.dump.is_empty());
+}
+
+#[test]
+fn dump_depth() {
+  let mut secd = machine("(letrec f (lambda n (+ 1 (f n))) (f 0))",
+                         Limits { dump_depth: Some(50), ..Limits::default() });
+  assert_eq!(exceeded(&mut secd).limit, Limit::DumpDepth);
+  assert_eq!(secd.dump.len(), 51);
+}
+
+#[test]
+fn stack_size() {
+  let mut secd = machine("(cons 1 (cons 2 (cons 3 (cons 4 nil))))",
+                         Limits { stack_size: Some(3), ..Limits::default() });
+  assert_eq!(exceeded(&mut secd).limit, Limit::StackSize);
+}
+
+#[test]
+fn allocations() {
+  let mut secd = machine("(letrec f (lambda n (f (cons n n))) (f 0))",
+                         Limits { allocations: Some(1000), ..Limits::default() });
+  let e = exceeded(&mut secd);
+  assert_eq!(e.limit, Limit::Allocations);
+  assert!(e.to_string().contains("limit exceeded: allocations (1000)"));
+
+  let mut secd = machine("(make-vector 1000000000 0)",
+                         Limits { allocations: Some(1000), ..Limits::default() });
+  assert_eq!(exceeded(&mut secd).limit, Limit::Allocations);
+
+  // a loop that drops what it allocates is still capped
+  let mut secd = machine("(letrec f (lambda n (let _ (cons n n) (f n))) (f 0))",
+                         Limits { allocations: Some(1000), ..Limits::default() });
+  assert_eq!(exceeded(&mut secd).limit, Limit::Allocations);
+}
+
+#[test]
+fn read_and_eval_allocations() {
+  // the 6 of the vector, then 2 conses for the list read from it
+  let src = "(read #(#\\( #\\1 #\\space #\\2 #\\)))";
+  let mut secd = machine(src, Limits::default());
+  secd.run().unwrap();
+  assert_eq!(secd.allocated, 8);
+  let mut secd = machine(src, Limits { allocations: Some(7), ..Limits::default() });
+  let e = exceeded(&mut secd);
+  assert_eq!((e.limit, e.info), (Limit::Allocations, [1, 6]));
+
+  // the 2 of the vector, then LDC 1 RET compiled by eval
+  let mut secd = machine("(eval (read #(#\\1)))", Limits::default());
+  assert_eq!(secd.run().unwrap(), Value::Int(1));
+  assert_eq!(secd.allocated, 4);
+}
+
+#[test]
+fn interpreter() {
+  let mut interp = Interpreter::new();
+  interp.set_limits(Limits { instructions: Some(1000), ..Limits::default() });
+  assert!(interp.eval_str("(define loop (lambda n (loop n)))").is_ok());
+  assert!(interp.eval_str("(loop 0)").unwrap_err().downcast_ref::<LimitExceeded>().is_some());
+  assert!(interp.eval_str("(+ 1 2)").is_ok());
+
+  // the forms of one eval_str share the budget
+  let forms = "(define inc (lambda n (+ n 1))) ".to_string() + &"(inc 1) ".repeat(200);
+  assert!(interp.eval_str(&forms).unwrap_err().downcast_ref::<LimitExceeded>().is_some());
+  assert!(interp.eval_str(&"(inc 1) ".repeat(100)).is_ok());
+}
+
+#[test]
+fn nested_calls() {
+  // frames of the callers a native function calls back from count too
+  let mut interp = Interpreter::new();
+  interp.register_fn_with_vm("apply1", 2, |vm, args| vm.call(&args[0], &args[1..]));
+  interp.set_limits(Limits { dump_depth: Some(50), ..Limits::default() });
+  let e = interp.eval_str("(define f (lambda n (apply1 f (+ n 1)))) (f 0)").unwrap_err();
+  assert_eq!(e.downcast_ref::<LimitExceeded>().expect("LimitExceeded").limit, Limit::DumpDepth);
+
+  interp.set_limits(Limits { stack_size: Some(100), ..Limits::default() });
+  let e = interp.eval_str("(define g (lambda n (cons n (apply1 g (+ n 1))))) (g 0)").unwrap_err();
+  assert_eq!(e.downcast_ref::<LimitExceeded>().expect("LimitExceeded").limit, Limit::StackSize);
+
+  // and however high the limits, nesting stops before the native stack runs out
+  interp.set_limits(Limits { dump_depth: Some(1000), ..Limits::default() });
+  let e = interp.eval_str("(f 0)").unwrap_err();
+  assert!(e.to_string().ends_with("vm error: call: more than 100 nested calls"), "{}", e);
+  interp.set_limits(Limits::default());
+  assert!(interp.eval_str("(f 0)").unwrap_err().to_string().contains("more than 100 nested calls"));
+}
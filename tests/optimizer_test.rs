@@ -0,0 +1,90 @@
+extern crate secd;
+use secd::*;
+use secd::data::Code;
+
+fn run(code: Code) -> (String, String) {
+  let (out, buf) = Output::buffer();
+  let mut interp = Interpreter::new();
+  interp.set_output(out);
+  let r = match interp.eval_code(code) {
+    Ok(val) => printer::write(&val),
+    Err(e) => e.to_string(),
+  };
+  (r, buf.contents())
+}
+
+fn same(s: &str) {
+  let code = compile_lisp(s).unwrap();
+  assert_eq!(run(optimize(&code)), run(code), "{}", s);
+}
+
+#[test]
+fn fold() {
+  let code = optimize(&compile_lisp("(+ 1 (- 5 2))").unwrap());
+  assert_eq!(disassemble(&code), "     0  1:3     LDC 4\n");
+
+  let code = optimize(&compile_lisp("(char=? (integer->char (char->integer #\\a)) #\\a)").unwrap());
+  assert_eq!(disassemble(&code), "     0  1:8     LDC true\n");
+}
+
+#[test]
+fn dead_branches() {
+  let code = optimize(&compile_lisp("(if (eq 1 1) (puts 1) (puts 2))").unwrap());
+  assert_eq!(disassemble(&code), "     0  1:21    LDC 1
+     1  1:19    PUTS
+");
+
+  let code = optimize(&compile_lisp("(if (eq 1 2) 1 (if true 2 3))").unwrap());
+  assert_eq!(disassemble(&code), "     0  1:26    LDC 2\n");
+}
+
+#[test]
+fn unused_lets() {
+  let code = optimize(&compile_lisp("(let x 1 (let f (lambda y y) 2))").unwrap());
+  assert_eq!(disassemble(&code), "     0  1:31    LDC 2\n");
+
+  // effects stay, and so do names that are used
+  let code = optimize(&compile_lisp("(let x 1 (let y (puts 2) x))").unwrap());
+  assert!(disassemble(&code).contains("PUTS"));
+  assert!(disassemble(&code).contains("LET x"));
+  let code = optimize(&compile_lisp("(let x 1 (eval (read #(#\\x))))").unwrap());
+  assert!(disassemble(&code).contains("LET x"));
+}
+
+#[test]
+fn overflow_not_folded() {
+  let code = optimize(&compile_lisp("(+ 2147483647 1)").unwrap());
+  assert!(disassemble(&code).contains("ADD"));
+  same("(integer->char (- 0 1))");
+}
+
+#[test]
+fn semantics() {
+  same(r#"
+    (letrec fib
+      (lambda n
+        (if (eq n 0)
+          0
+        (if (eq n 1)
+          1
+        (+ (fib (- n 1)) (fib (- n 2))))))
+      (puts (fib 15)))
+  "#);
+  same(r#"
+    (let a 0
+    (let f (lambda () a)
+    (let _ (set! a (+ 1 2))
+    (let unused (lambda x (puts x))
+    (if (eq (f) 3) (puts #\y) (puts #\n))))))
+  "#);
+  same(r#"
+    (let h (make-hash-table)
+    (let _ (hash-set! h (+ 1 1) (if false 1 (- 9 2)))
+    (let v (vector 1 (+ 2 3) (char->integer #\a))
+    (cons (hash-ref h 2) (vector-ref v (- 3 2))))))
+  "#);
+  same("(define g (lambda x (if true (+ x 1) x))) (puts (g 1)) (let y 3 (g y))");
+  same("(let x (+ 1 2) (eval (read #(#\\x))))");
+  same("(if (eq (+ 1 1) 2) (car nil) 0)");
+  same("(let f (lambda (a b) (if (char<? a b) (cons a b) nil)) (f #\\a (integer->char 98)))");
+}
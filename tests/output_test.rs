@@ -0,0 +1,30 @@
+extern crate secd;
+use secd::*;
+use std::rc::Rc;
+use std::cell::RefCell;
+
+#[test]
+fn buffer() {
+  let (out, buf) = Output::buffer();
+  let mut secd = SECD::new(
+    Compiler::new().compile(
+      Parser::new(&"(cons (puts (cons 1 2)) (write #\\a))".into()).parse().unwrap()
+    ).unwrap()
+  );
+  secd.output = out;
+
+  assert!(secd.run().is_ok());
+  assert_eq!(buf.contents(), "(1 . 2)\n#\\a\n");
+}
+
+#[test]
+fn callback() {
+  let lines = Rc::new(RefCell::new(Vec::new()));
+  let sink = lines.clone();
+
+  let mut interp = Interpreter::new();
+  interp.set_output(Output::callback(move |s| sink.borrow_mut().push(s.to_string())));
+  interp.eval_str("(puts 1) (puts (+ 1 1))").unwrap();
+
+  assert_eq!(*lines.borrow(), vec!["1\n".to_string(), "2\n".to_string()]);
+}
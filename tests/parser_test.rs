@@ -7,3 +7,37 @@ fn parser() {
   assert!(a.is_ok());
   assert_eq!(format!("{}", a.unwrap()), "(a 0 ab 12 (a (b) ()) () ab ())".to_string());
 }
+
+#[test]
+fn vector() {
+  let a = Parser::new(&"(a #(1 #(b)) #())".into()).parse();
+  assert!(a.is_ok());
+  assert_eq!(format!("{}", a.unwrap()), "(a #(1 #(b)) #())".to_string());
+}
+
+#[test]
+fn char_() {
+  let a = Parser::new(&"(#\\a #\\( #\\space #\\newline)".into()).parse();
+  assert!(a.is_ok());
+  assert_eq!(format!("{}", a.unwrap()), "(#\\a #\\( #\\space #\\newline)".to_string());
+  assert!(Parser::new(&"(#\\abc)".into()).parse().is_err());
+
+  // names and literals are read by char, not by byte
+  assert_eq!(format!("{}", Parser::new(&"(#\\é #\\λ)".into()).parse().unwrap()), "(#\\é #\\λ)");
+  for &(src, msg) in &[("#\\aé", "unknown char name 'aé' in [1, 5]"),
+                       ("(a é)", "lex unexpect token 'é' in [1, 4]"),
+                       ("(1² 2)", "bad int '1²' in [1, 4]"),
+                       ("99999999999", "bad int '99999999999' in [1, 12]")] {
+    assert_eq!(Parser::new(&src.into()).parse().unwrap_err().to_string(), msg, "{}", src);
+  }
+}
+
+#[test]
+fn read() {
+  let mut p = Parser::new(&"(a b) 12 (c".into());
+  assert_eq!(format!("{}", p.read().unwrap().unwrap()), "(a b)".to_string());
+  assert_eq!(format!("{}", p.read().unwrap().unwrap()), "12".to_string());
+  assert!(p.read().is_err());
+  assert_eq!(p.unclosed(), 1);
+  assert!(Parser::new(&"".into()).parse().is_err());
+}
@@ -0,0 +1,56 @@
+extern crate secd;
+use secd::*;
+use secd::printer;
+
+fn eval(s: &str) -> Value {
+  SECD::new(
+    Compiler::new().compile(
+      Parser::new(&s.into()).parse().unwrap()
+    ).unwrap()
+  ).run().unwrap()
+}
+
+#[test]
+fn list() {
+  assert_eq!(format!("{}", eval("(cons 1 (cons 2 (cons 3 nil)))")), "(1 2 3)");
+  assert_eq!(format!("{}", eval("(cons 1 2)")), "(1 . 2)");
+  assert_eq!(format!("{}", eval("(cons (cons 1 nil) (cons 2 3))")), "((1) 2 . 3)");
+  assert_eq!(format!("{}", eval("#(1 (cons 2 nil) (box 3))")), "#(1 (2) #&3)");
+}
+
+#[test]
+fn display_write() {
+  let a = eval("(cons #\\a (cons #\\space nil))");
+  assert_eq!(printer::display(&a), "(a  )");
+  assert_eq!(printer::write(&a), "(#\\a #\\space)");
+}
+
+#[test]
+fn cycle() {
+  let a = eval("(let a (cons 1 (cons 2 nil)) (let _ (set-cdr! (cdr a) a) a))");
+  assert_eq!(printer::write(&a), "#0=(1 2 . #0#)");
+
+  let b = eval("(let v (vector 1 2) (let _ (vector-set! v 0 v) (cons v v)))");
+  assert_eq!(printer::write(&b), "(#0=#(#0# 2) . #0#)");
+}
+
+#[test]
+fn depth() {
+  let mut s = String::from("nil");
+  for _ in 0..5 {
+    s = format!("(cons {} nil)", s);
+  }
+  let a = eval(&s);
+  let mut out = String::new();
+  printer::Printer::new(printer::Mode::Write).max_depth(2).print(&mut out, &a).unwrap();
+  assert_eq!(out, "(((...)))");
+}
+
+#[test]
+fn long_list() {
+  let mut l = Value::Nil;
+  for i in 0..100000 {
+    l = Value::cons(Value::Int(i), l);
+  }
+  assert!(printer::display(&l).starts_with("(99999 99998 "));
+}
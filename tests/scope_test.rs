@@ -0,0 +1,58 @@
+extern crate secd;
+use secd::*;
+
+fn warnings(s: &str) -> Vec<String> {
+  compile_lisp_with_diagnostics(s).unwrap().1.iter().map(|d| d.to_string()).collect()
+}
+
+#[test]
+fn unbound() {
+  for &(src, msg) in &[("(let a 1 (+ a b))", "1:16:compile error: unbound variable 'b'"),
+                       ("(lambda (x y) (z x))", "1:17:compile error: unbound variable 'z'"),
+                       ("(cons (let y 1 y) y)", "1:20:compile error: unbound variable 'y'"),
+                       ("(let f (lambda n (f n)) 0)", "1:20:compile error: unbound variable 'f'"),
+                       ("(set! q 1)", "1:8:compile error: unbound variable 'q'")] {
+    assert_eq!(compile_lisp(src).unwrap_err().to_string(), msg);
+  }
+
+  for src in &["(letrec f (lambda n (f n)) 0)",
+               "(define f (lambda n (if (eq n 0) 0 (f (- n 1))))) (f 2)",
+               "(let _ (define g 1) g)"] {
+    assert!(compile_lisp(src).is_ok(), "{}", src);
+  }
+}
+
+#[test]
+fn interpreter_globals() {
+  let mut interp = Interpreter::new();
+  interp.define("base", 10i32);
+  interp.register_fn("twice", 1, |args| Ok(Value::Int(i32::from_lisp(&args[0])? * 2)));
+  interp.eval_str("(define x (twice base))").unwrap();
+  assert_eq!(interp.eval_str("(+ x 1)").unwrap(), Value::Int(21));
+
+  // eval sees globals, including ones defined by the same form
+  assert_eq!(interp.eval_str("(let _ (define y 5) (eval (read #(#\\y))))").unwrap(), Value::Int(5));
+  assert!(interp.eval_str("(let z 1 (eval (read #(#\\z))))")
+                .unwrap_err()
+                .to_string()
+                .contains("compile error: unbound variable 'z'"));
+}
+
+#[test]
+fn shadowed_and_unused() {
+  assert_eq!(warnings("(let x 1\n  (let y 2\n    (let x 3 (lambda _ x))))"),
+             vec!["1:7:warning: unused binding 'x'",
+                  "2:9:warning: unused binding 'y'",
+                  "3:11:warning: 'x' shadows an outer binding"]);
+  assert_eq!(warnings("(lambda (a b) (letrec a (lambda _ a) b))"),
+             vec!["1:11:warning: unused binding 'a'",
+                  "1:24:warning: 'a' shadows an outer binding"]);
+
+  // names starting with `_` are never reported, and set! counts as a use
+  assert!(warnings("(let _ 1 (let _x 2 (let _ 3 (let y 4 (set! y 5)))))").is_empty());
+
+  let mut interp = Interpreter::new();
+  interp.eval_str("(let unused 0 1)").unwrap();
+  assert_eq!(interp.take_diagnostics().len(), 1);
+  assert!(interp.take_diagnostics().is_empty());
+}
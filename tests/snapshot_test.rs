@@ -0,0 +1,168 @@
+extern crate secd;
+use secd::*;
+use secd::data::Native;
+use std::rc::Rc;
+use std::cell::RefCell;
+
+fn machine(s: &str) -> SECD {
+  SECD::new(
+    Compiler::new().compile(
+      Parser::new(&s.into()).parse().unwrap()
+    ).unwrap()
+  )
+}
+
+fn finish(mut secd: SECD) -> Value {
+  match secd.run_for(1_000_000).unwrap() {
+    Status::Finished(val) => val,
+    Status::Paused => panic!("still running"),
+  }
+}
+
+#[test]
+fn resume() {
+  let src = "(letrec f (lambda n (if (eq n 0) 0 (+ 2 (f (- n 1))))) (f 50))";
+  let mut secd = machine(src);
+  assert_eq!(secd.run_for(200).unwrap(), Status::Paused);
+  assert!(!secd.dump.is_empty());
+
+  let bytes = secd.snapshot();
+  let restored = SECD::restore(&bytes).unwrap();
+  assert_eq!(restored.executed, 200);
+  assert_eq!(restored.snapshot(), bytes);
+  assert_eq!(finish(restored), Value::Int(100));
+  assert_eq!(finish(secd), Value::Int(100));
+}
+
+#[test]
+fn sharing() {
+  let src = r#"
+    (let make
+      (lambda ()
+        (let n 0
+          (cons (lambda () (set! n (+ n 1)))
+                (lambda () n))))
+    (let a (make)
+    (let b (make)
+    (cons a b))))
+  "#;
+  let mut secd = machine(src);
+  secd.run().unwrap();
+  let mut secd = SECD::restore(&secd.snapshot()).unwrap();
+  let pairs = secd.stack.last().unwrap().clone();
+
+  let car = |v: &Value| match v.as_lisp() {
+    Some(Lisp::Cons(a, _)) => a.borrow().clone(),
+    _ => panic!("expected cons"),
+  };
+  let cdr = |v: &Value| match v.as_lisp() {
+    Some(Lisp::Cons(_, d)) => d.borrow().clone(),
+    _ => panic!("expected cons"),
+  };
+
+  let (a, b) = (car(&pairs), cdr(&pairs));
+  let (inc, get) = (car(&a), cdr(&a));
+  secd.call(&inc, &[]).unwrap();
+  secd.call(&inc, &[]).unwrap();
+  assert_eq!(secd.call(&get, &[]).unwrap(), Value::Int(2));
+  assert_eq!(secd.call(&cdr(&b), &[]).unwrap(), Value::Int(0));
+
+  match (inc.as_lisp(), car(&b).as_lisp()) {
+    (Some(Lisp::Closure(_, x, _, _)), Some(Lisp::Closure(_, y, _, _))) => assert!(Rc::ptr_eq(x, y)),
+    _ => panic!("expected closures"),
+  }
+}
+
+#[test]
+fn cycle() {
+  let mut secd = machine("(let a (cons 1 (cons 2 nil)) (let _ (set-cdr! (cdr a) a) a))");
+  secd.run().unwrap();
+  let secd = SECD::restore(&secd.snapshot()).unwrap();
+  let a = secd.stack.last().unwrap();
+  assert_eq!(format!("{}", a), "#0=(1 2 . #0#)");
+}
+
+#[test]
+fn natives() {
+  let double = Native {
+    name: "double".to_string(),
+    arity: 1,
+    fun: Rc::new(|_, args| match args[0] {
+      Value::Int(n) => Ok(Value::Int(n * 2)),
+      _ => Err(From::from("expected int")),
+    }),
+  };
+
+  let mut compiler = Compiler::new();
+  compiler.declare(Symbol::intern("double"));
+  let mut secd = SECD::new(
+    compiler.compile(Parser::new(&"(+ 1 (double 20))".into()).parse().unwrap()).unwrap()
+  );
+  secd.globals.insert(Symbol::intern("double"),
+                      Rc::new(RefCell::new(Value::new(Lisp::Native(double.clone())))));
+  secd.run_for(3).unwrap();
+  let bytes = secd.snapshot();
+
+  let err = SECD::restore(&bytes).unwrap_err();
+  assert_eq!(format!("{}", err), "snapshot error: unknown native 'double'");
+
+  let secd = SECD::restore_with(&bytes, |name| {
+    if name == "double" { Some(double.clone()) } else { None }
+  }).unwrap();
+  assert_eq!(finish(secd), Value::Int(41));
+}
+
+#[test]
+fn corrupt() {
+  let mut secd = machine("(+ 1 2)");
+  secd.step().unwrap();
+  let bytes = secd.snapshot();
+
+  let err = SECD::restore(b"garbage").unwrap_err();
+  assert_eq!(format!("{}", err), "snapshot error: bad magic number");
+
+  let mut newer = bytes.clone();
+  newer[8] = 99;
+  let err = SECD::restore(&newer).unwrap_err();
+  assert_eq!(format!("{}", err), "snapshot error: unsupported version 99 (expected 4)");
+
+  let err = SECD::restore(&bytes[..bytes.len() - 3]).unwrap_err();
+  assert_eq!(format!("{}", err), "snapshot error: unexpected end of data");
+
+  // whatever restores must also run without crashing
+  let src = "(letrec f (lambda (l v) (if (eq l nil) (vector-ref v 1) (cons (car l) (f (cdr l) v))))
+               (f (cons 1 (cons 2 (cons 3 nil))) #(1 2 3)))";
+  let mut secd = machine(src);
+  secd.run_for(30).unwrap();
+  let bytes = secd.snapshot();
+  for i in 12..bytes.len() {
+    for &x in &[0xff, 0x01, 0x02, 0x10] {
+      let mut bad = bytes.clone();
+      bad[i] ^= x;
+      if let Ok(mut secd) = SECD::restore(&bad) {
+        secd.limits = Limits { instructions: Some(10_000), allocations: Some(10_000), ..Limits::default() };
+        let _ = secd.run_for(10_000);
+      }
+    }
+  }
+}
+
+#[test]
+fn inconsistent() {
+  // the machine must resume with the values its next instruction pops
+  let mut secd = machine("(+ 1 2)");
+  secd.run_for(2).unwrap();
+  secd.stack.pop();
+  let err = SECD::restore(&secd.snapshot()).unwrap_err();
+  assert_eq!(format!("{}", err), "snapshot error: instruction 2 needs 2 values on the stack, got 1");
+
+  let mut secd = machine("(lambda x x)");
+  secd.run().unwrap();
+  let f = match secd.stack.pop().unwrap().as_lisp() {
+    Some(Lisp::Closure(names, code, _, env)) => Lisp::Closure(names.clone(), code.clone(), 0, env.clone()),
+    _ => panic!("expected closure"),
+  };
+  secd.stack.push(Value::new(f));
+  let err = SECD::restore(&secd.snapshot()).unwrap_err();
+  assert_eq!(format!("{}", err), "snapshot error: closure entry 0 is not a closure body");
+}
@@ -0,0 +1,42 @@
+extern crate secd;
+use secd::*;
+
+fn machine(s: &str) -> SECD {
+  SECD::new(
+    Compiler::new().compile(
+      Parser::new(&s.into()).parse().unwrap()
+    ).unwrap()
+  )
+}
+
+#[test]
+fn step() {
+  let mut secd = machine("(+ 1 2)");
+  assert_eq!(secd.step().unwrap(), Status::Paused);
+  assert_eq!(secd.stack, vec![Value::Int(1)]);
+  assert_eq!(secd.step().unwrap(), Status::Paused);
+  assert_eq!(secd.step().unwrap(), Status::Finished(Value::Int(3)));
+  assert_eq!(secd.step().unwrap(), Status::Finished(Value::Int(3)));
+}
+
+#[test]
+fn run_for() {
+  let src = "(letrec f (lambda n (if (eq n 0) 0 (+ 1 (f (- n 1))))) (f 100))";
+  let mut a = machine(src);
+  let mut b = machine(src);
+
+  let mut result = None;
+  let mut slices = 0;
+  while result.is_none() {
+    slices += 1;
+    let ra = a.run_for(10).unwrap();
+    let rb = b.run_for(10).unwrap();
+    if let (Status::Finished(x), Status::Finished(y)) = (ra, rb) {
+      result = Some((x, y));
+    }
+  }
+
+  assert!(slices > 10);
+  assert_eq!(result, Some((Value::Int(100), Value::Int(100))));
+  assert_eq!(a.executed, b.executed);
+}
@@ -0,0 +1,20 @@
+extern crate secd;
+use secd::*;
+
+#[test]
+fn intern() {
+  let a = Symbol::intern("foo");
+  assert_eq!(a, Symbol::intern("foo"));
+  assert_ne!(a, Symbol::intern("bar"));
+  assert_eq!(a.as_str(), "foo");
+  assert_eq!(format!("{} {:?}", a, a), "foo \"foo\"");
+}
+
+#[test]
+fn read_symbols() {
+  let val = run_lisp("(eq (read #(#\\f #\\o #\\o)) (read #(#\\f #\\o #\\o)))").unwrap();
+  assert_eq!(val, Value::True);
+
+  let val = run_lisp("(eval (read #(#\\( #\\l #\\a #\\m #\\b #\\d #\\a #\\space #\\x #\\space #\\x #\\))))").unwrap();
+  assert_eq!(format!("{}", val), "#<closure (x)>");
+}
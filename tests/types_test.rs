@@ -0,0 +1,85 @@
+extern crate secd;
+use secd::*;
+
+fn infer(s: &str) -> String {
+  match typecheck_lisp(s) {
+    Ok(ty) => ty.to_string(),
+    Err(e) => e.to_string(),
+  }
+}
+
+#[test]
+fn infers() {
+  for &(src, ty) in &[("(+ 1 2)", "int"),
+                      ("(eq 1 #\\a)", "bool"),
+                      ("(lambda x x)", "('a -> 'a)"),
+                      ("(lambda (f x) (f (f x)))", "(('a -> 'a) 'a -> 'a)"),
+                      ("(lambda p (cons (cdr p) (car p)))", "((pair 'a 'b) -> (pair 'b 'a))"),
+                      ("(let v #(1 2) (vector-ref v 0))", "int"),
+                      ("(if (eq 1 2) nil nil)", "(list 'a)")] {
+    assert_eq!(infer(src), ty, "{}", src);
+  }
+
+  let fib = "(letrec fib (lambda n (if (eq n 0) 0 (if (eq n 1) 1 (+ (fib (- n 1)) (fib (- n 2)))))) fib)";
+  assert_eq!(infer(fib), "(int -> int)");
+  assert_eq!(infer("(define inc (lambda n (+ n 1))) (inc 2)"), "int");
+}
+
+#[test]
+fn lists() {
+  // a list built by cons is a chain of pairs, which unifies with a list
+  for &(src, ty) in &[("(cons 1 (cons 2 nil))", "(pair int (pair int (list 'a)))"),
+                      ("(car (cdr (cons 1 (cons true nil))))", "bool"),
+                      ("(lambda l (cons (car l) (cdr l)))", "((pair 'a 'b) -> (pair 'a 'b))"),
+                      ("(letrec len (lambda l (if (eq l nil) 0 (+ 1 (len (cdr l))))) (len (cons 1 (cons 2 nil))))", "int"),
+                      ("(letrec len (lambda l (if (eq l nil) 0 (+ 1 (len (cdr l))))) len)", "((list 'a) -> int)"),
+                      ("(letrec map (lambda (f l) (if (eq l nil) nil (cons (f (car l)) (map f (cdr l))))) map)",
+                       "(('a -> 'b) (list 'a) -> (list 'b))")] {
+    assert_eq!(infer(src), ty, "{}", src);
+  }
+
+  assert_eq!(infer("(define sum (lambda l (if (eq l nil) 0 (+ (car l) (sum (cdr l)))))) (sum (cons 1 (cons #\\a nil)))"),
+             "1:79:type error: expected int, got char");
+}
+
+#[test]
+fn let_polymorphism() {
+  assert_eq!(infer("(let id (lambda x x) (cons (id 1) (id true)))"), "(pair int bool)");
+  assert_eq!(infer("(define id (lambda x x)) (cons (id nil) (id #\\a))"), "(pair (list 'a) char)");
+
+  // lambda-bound names and cells stay monomorphic
+  assert_eq!(infer("((lambda id (cons (id 1) (id true))) (lambda x x))"),
+             "1:34:type error: expected int, got bool");
+  assert_eq!(infer("(let b (box (lambda x x)) (let _ (set-box! b (lambda x (+ x 1))) ((unbox b) true)))"),
+             "1:81:type error: expected int, got bool");
+
+  // so do names set! assigns to
+  assert_eq!(infer("(let f (lambda (y) y) (let _ (set! f (lambda (y) 5)) (if (f true) 1 2)))"),
+             "1:65:type error: expected int, got bool");
+  assert_eq!(infer("(define f (lambda (y) y)) (define g (lambda () (set! f (lambda (y) 5)))) (if (f true) 1 2)"),
+             "1:53:type error: cannot set! 'f', whose type is polymorphic");
+  assert_eq!(infer("(let _ (define f (lambda (y) y)) (let _ (set! f (lambda (y) 5)) (if (f true) 1 2)))"),
+             "1:76:type error: expected int, got bool");
+  assert_eq!(infer("(define f (lambda (y) y))\n(set! f (lambda (y) 5))"),
+             "2:6:type error: cannot set! 'f', whose type is polymorphic");
+
+  // shadowing binders are separate names
+  assert_eq!(infer("(let x 1 (cons (let x true x) (+ x 1)))"), "(pair bool int)");
+  assert_eq!(format!("{}", run_lisp("(let x 1 (cons (let x true x) (+ x 1)))").unwrap()), "(true . 2)");
+}
+
+#[test]
+fn errors() {
+  for &(src, msg) in &[("(+ 1 nil)", "1:9:type error: expected int, got (list 'a)"),
+                       ("(car 5)", "1:7:type error: expected (pair 'a 'b), got int"),
+                       ("(let f (lambda x (+ x 1))\n  (f #\\a))", "2:9:type error: expected int, got char"),
+                       ("(if 1 2 3)", "1:6:type error: expected bool, got int"),
+                       ("(if true 2 nil)", "1:15:type error: expected int, got (list 'a)"),
+                       ("((lambda (a b) a) 1)", "1:9:type error: expected 2 args, got 1"),
+                       ("(lambda x (x x))", "1:13:type error: infinite type 'a = ('a -> 'b)")] {
+    assert_eq!(infer(src), msg, "{}", src);
+  }
+
+  // unknown globals such as natives can have any type
+  assert_eq!(infer("(let _ (define g 1) (+ g 2))"), "int");
+}
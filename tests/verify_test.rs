@@ -0,0 +1,46 @@
+extern crate secd;
+use secd::*;
+use secd::data::{CodeOP, CodeOPInfo};
+use secd::data::CodeOP::*;
+use secd::verify::verify;
+
+use std::rc::Rc;
+
+fn check(ops: Vec<CodeOP>) -> Result<(), String> {
+  let code: Vec<_> = ops.into_iter().map(|op| CodeOPInfo { info: [1, 1], op }).collect();
+  verify(&code).map_err(|e| format!("{}: {}", e.at, e.msg))
+}
+
+fn closure(n: usize) -> CodeOP {
+  CLOSURE(Rc::new(Box::new([])), n)
+}
+
+#[test]
+fn accepts_compiled_code() {
+  for src in &["(letrec f (lambda n (if (eq n 0) nil (f (- n 1)))) (f 3))",
+               "(let v (vector 1 2 3) (cons (vector-ref v 0) (eval (read #(#\\1)))))",
+               "(define g (lambda (a b) (if a b (lambda _ a)))) 1 2"] {
+    let code = compile_lisp(src).unwrap();
+    assert!(verify(&code).is_ok(), "{}", src);
+    assert!(verify(&optimize(&code)).is_ok(), "{}", src);
+  }
+}
+
+#[test]
+fn rejects() {
+  assert_eq!(check(vec![JMP(1)]), Err("0: jump of 1 runs past the end of the code".to_string()));
+  assert_eq!(check(vec![closure(2), LDC(Value::Nil), JMP(1), RET]),
+             Err("2: jump of 1 runs past the end of its closure body".to_string()));
+  assert_eq!(check(vec![LDC(Value::True), JMPF(1), closure(2), LDC(Value::Nil), RET]),
+             Err("1: jump of 1 lands inside a closure body".to_string()));
+  assert_eq!(check(vec![closure(1), LDC(Value::Nil)]),
+             Err("1: closure body does not end in RET".to_string()));
+  assert_eq!(check(vec![closure(0)]), Err("0: closure body does not end in RET".to_string()));
+  assert_eq!(check(vec![closure(1), RET]), Err("1: RET needs 1 values on the stack, got 0".to_string()));
+  assert_eq!(check(vec![LDC(Value::True), JMPF(1), LDC(Value::Nil), LDC(Value::Nil), CONS]),
+             Err("4: CONS needs 2 values on the stack, got 1".to_string()));
+
+  // unreachable code only needs to be well formed
+  assert!(check(vec![LDC(Value::Nil), JMP(1), ADD, LDC(Value::Nil)]).is_ok());
+  assert!(check(vec![JMP(1), JMP(5)]).is_err());
+}
@@ -1,6 +1,8 @@
 extern crate secd;
 use secd::*;
-use std::rc::Rc;
+
+use std::hash::{Hash, Hasher};
+use std::cell::RefCell;
 
 #[test]
 fn let_() {
@@ -9,12 +11,12 @@ fn let_() {
   "#;
   let r = SECD::new(
     Compiler::new().compile(
-      &Parser::new(&s.into()).parse().unwrap()
+      Parser::new(&s.into()).parse().unwrap()
     ).unwrap()
   ).run();
 
   assert!(r.is_ok());
-  assert_eq!(r.unwrap(), Rc::new(Lisp::Int(0)));
+  assert_eq!(r.unwrap(), Value::Int(0));
 }
 
 #[test]
@@ -24,12 +26,12 @@ fn let_lambda_ap() {
   "#;
   let r = SECD::new(
     Compiler::new().compile(
-      &Parser::new(&s.into()).parse().unwrap()
+      Parser::new(&s.into()).parse().unwrap()
     ).unwrap()
   ).run();
 
   assert!(r.is_ok());
-  assert_eq!(r.unwrap(), Rc::new(Lisp::Int(0)));
+  assert_eq!(r.unwrap(), Value::Int(0));
 }
 
 #[test]
@@ -39,7 +41,7 @@ fn letrec_rap() {
   "#;
   let r = SECD::new(
     Compiler::new().compile(
-      &Parser::new(&s.into()).parse().unwrap()
+      Parser::new(&s.into()).parse().unwrap()
     ).unwrap()
   ).run();
 
@@ -53,7 +55,7 @@ fn sel_eq() {
   "#;
   let r = SECD::new(
     Compiler::new().compile(
-      &Parser::new(&s.into()).parse().unwrap()
+      Parser::new(&s.into()).parse().unwrap()
     ).unwrap()
   ).run();
 
@@ -70,12 +72,12 @@ fn cons_car_cdr() {
   "#;
   let r = SECD::new(
     Compiler::new().compile(
-      &Parser::new(&s.into()).parse().unwrap()
+      Parser::new(&s.into()).parse().unwrap()
     ).unwrap()
   ).run();
 
   assert!(r.is_ok());
-  assert_eq!(*r.unwrap(), Lisp::True);
+  assert_eq!(r.unwrap(), Value::True);
 }
 
 #[test]
@@ -87,11 +89,238 @@ fn add_sub() {
   "#;
   let r = SECD::new(
     Compiler::new().compile(
-      &Parser::new(&s.into()).parse().unwrap()
+      Parser::new(&s.into()).parse().unwrap()
+    ).unwrap()
+  ).run();
+
+  assert!(r.is_ok());
+  assert_eq!(r.unwrap(), Value::True);
+}
+
+
+#[test]
+fn set_captured() {
+  let s = r#"
+    (let a 0
+    (let f (lambda () a)
+    (let _ (set! a 1)
+    (f))))
+  "#;
+  let r = SECD::new(
+    Compiler::new().compile(
+      Parser::new(&s.into()).parse().unwrap()
+    ).unwrap()
+  ).run();
+
+  assert!(r.is_ok());
+  assert_eq!(r.unwrap(), Value::Int(1));
+}
+
+#[test]
+fn set_counter() {
+  let s = r#"
+    (let counter
+      (lambda ()
+        (let n 0
+          (lambda () (let _ (set! n (+ n 1)) n))))
+    (let c (counter)
+    (let _ (c)
+    (c))))
+  "#;
+  let r = SECD::new(
+    Compiler::new().compile(
+      Parser::new(&s.into()).parse().unwrap()
+    ).unwrap()
+  ).run();
+
+  assert!(r.is_ok());
+  assert_eq!(r.unwrap(), Value::Int(2));
+}
+
+#[test]
+fn set_car_cdr_box() {
+  let s = r#"
+    (let a (cons 0 0)
+    (let b (box 0)
+    (let _ (set-car! a 1)
+    (let _ (set-cdr! a 2)
+    (let _ (set-box! b (+ (car a) (cdr a)))
+    (unbox b))))))
+  "#;
+  let r = SECD::new(
+    Compiler::new().compile(
+      Parser::new(&s.into()).parse().unwrap()
+    ).unwrap()
+  ).run();
+
+  assert!(r.is_ok());
+  assert_eq!(r.unwrap(), Value::Int(3));
+}
+
+#[test]
+fn vector() {
+  let s = r#"
+    (let v (make-vector 3 0)
+    (let _ (vector-set! v 1 #(1 (+ 1 1)))
+    (let w (vector-ref v 1)
+    (+ (vector-length v) (vector-ref w 1)))))
+  "#;
+  let r = SECD::new(
+    Compiler::new().compile(
+      Parser::new(&s.into()).parse().unwrap()
+    ).unwrap()
+  ).run();
+
+  assert!(r.is_ok());
+  assert_eq!(r.unwrap(), Value::Int(5));
+}
+
+#[test]
+fn hash_table() {
+  let s = r#"
+    (let h (make-hash-table)
+    (let _ (hash-set! h (cons 1 2) 10)
+    (let _ (hash-set! h #(3) 20)
+    (let _ (hash-set! h 4 30)
+    (let _ (hash-remove! h 4)
+    (if (hash-has-key? h 4)
+      0
+      (+ (hash-count h)
+         (+ (hash-ref h (cons 1 2)) (hash-ref h #(3))))))))))
+  "#;
+  let r = SECD::new(
+    Compiler::new().compile(
+      Parser::new(&s.into()).parse().unwrap()
+    ).unwrap()
+  ).run();
+
+  assert!(r.is_ok());
+  assert_eq!(r.unwrap(), Value::Int(32));
+}
+
+#[test]
+fn char_() {
+  let s = r#"
+    (let a (integer->char (+ (char->integer #\a) 1))
+    (if (char=? a #\b)
+      (if (char<? #\space a) #\newline #\x)
+      #\y))
+  "#;
+  let r = SECD::new(
+    Compiler::new().compile(
+      Parser::new(&s.into()).parse().unwrap()
+    ).unwrap()
+  ).run();
+
+  assert!(r.is_ok());
+  assert_eq!(r.unwrap(), Value::Char('\n'));
+}
+
+#[test]
+fn read_eval() {
+  let s = r#"
+    (let a (read #(#\( #\+ #\space #\1 #\space #\2 #\)))
+    (let b (read (cons #\4 (cons #\0 nil)))
+    (cons (car a) (+ (eval a) b))))
+  "#;
+  let r = SECD::new(
+    Compiler::new().compile(
+      Parser::new(&s.into()).parse().unwrap()
+    ).unwrap()
+  ).run();
+
+  assert!(r.is_ok());
+  assert_eq!(format!("{}", r.unwrap()), "(+ . 43)");
+}
+
+#[test]
+fn immediates_as_keys() {
+  let s = r#"
+    (let h (make-hash-table)
+    (let _ (hash-set! h 1 #\a)
+    (let _ (hash-set! h (car (read #(#\( #\x #\)))) true)
+    (cons (hash-ref h (+ 0 1)) (hash-ref h (read #(#\x)))))))
+  "#;
+  let r = SECD::new(
+    Compiler::new().compile(
+      Parser::new(&s.into()).parse().unwrap()
     ).unwrap()
   ).run();
 
   assert!(r.is_ok());
-  assert_eq!(*r.unwrap(), Lisp::True);
+  assert_eq!(r.unwrap(), Value::cons(Value::Char('a'), Value::True));
+}
+
+#[test]
+fn cyclic_and_long_lists() {
+  let s = r#"
+    (let a (cons 1 nil)
+    (let _ (set-cdr! a a)
+    (let b (cons 1 (cons 1 nil))
+    (let _ (set-cdr! (cdr b) b)
+    (let c (cons 1 (cons 2 nil))
+    (let _ (set-cdr! (cdr c) c)
+    (let h (make-hash-table)
+    (let _ (hash-set! h a 10)
+    (cons (cons (eq a b) (eq a c)) (hash-ref h b))))))))))
+  "#;
+  let r = SECD::new(
+    Compiler::new().compile(
+      Parser::new(&s.into()).parse().unwrap()
+    ).unwrap()
+  ).run();
+
+  assert_eq!(format!("{}", r.unwrap()), "((true . false) . 10)");
+
+  let long = || Value::from_vec((0..200_000).map(Value::Int).collect());
+  assert_eq!(long(), long());
+  let hash = |v: &Value| {
+    let mut h = std::collections::hash_map::DefaultHasher::new();
+    v.hash(&mut h);
+    h.finish()
+  };
+  assert_eq!(hash(&long()), hash(&long()));
+}
+
+#[test]
+fn cyclic_read_eval() {
+  for &(src, msg) in &[("(let l (cons #\\a nil) (let _ (set-cdr! l l) (read l)))",
+                        "1:50:vm error: READ: expected list or vector of chars"),
+                       ("(let l (cons #\\a nil) (let _ (set-cdr! l l) (eval l)))",
+                        "1:50:vm error: EVAL: can not eval dotted list #0=(a . #0#)"),
+                       ("(let l (cons 1 nil) (let _ (set-car! l l) (eval l)))",
+                        "1:48:vm error: EVAL: can not eval self-referential data #0=(#0#)"),
+                       ("(let v #(1 2) (let _ (vector-set! v 0 (cons v nil)) (eval v)))",
+                        "1:58:vm error: EVAL: can not eval self-referential data #0=#((#0#) 2)")] {
+    assert_eq!(run_lisp(src).unwrap_err().to_string(), msg, "{}", src);
+  }
+
+  // shared but acyclic data is fine
+  let src = "(let n (read #(#\\( #\\+ #\\space #\\1 #\\space #\\2 #\\))) (eval (cons (car n) (cons n (cons n nil)))))";
+  assert_eq!(run_lisp(src).unwrap(), Value::Int(6));
 }
 
+#[test]
+fn drop_deep_nesting() {
+  // nesting through anything but the cdr must not overflow when dropped either
+  let wraps: Vec<fn(Value) -> Value> = vec![
+    |v| Value::cons(v, Value::Nil),
+    |v| Value::new(Lisp::Box(RefCell::new(v))),
+    |v| Value::new(Lisp::Vector(RefCell::new(vec![Value::Int(0), v]))),
+    |v| Value::new(Lisp::List(vec![v])),
+    |v| Value::new(Lisp::HashTable(RefCell::new(Some((Value::Int(0), v)).into_iter().collect()))),
+  ];
+  for wrap in &wraps {
+    let deep = (0..200_000).fold(Value::Nil, |v, _| wrap(v));
+    drop(deep);
+  }
+
+  // and through the environments of closures
+  let s = "(letrec f (lambda (n g) (if (eq n 0) g (f (- n 1) (lambda () g)))) (f 200000 nil))";
+  let r = SECD::new(
+    Compiler::new().compile(
+      Parser::new(&s.into()).parse().unwrap()
+    ).unwrap()
+  ).run();
+  drop(r.unwrap());
+}